@@ -0,0 +1,266 @@
+//! A [`Connection`] bound to a tokio transport, receiving messages through a [`Stream`] and
+//! sending them through a [`Sink`].
+//!
+//! [`Stream`]: futures_core::Stream
+//! [`Sink`]: futures_sink::Sink
+
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
+use std::io::ErrorKind;
+use std::vec::Vec;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::stream::StreamError;
+use crate::{buffer, protocol, Connection, DeserializeError};
+
+type Error<Protocol> = StreamError<
+    <Protocol as protocol::Protocol>::SerializerError,
+    <Protocol as protocol::Protocol>::DeserializerError,
+>;
+
+/// Sends `Outgoing` messages and receives `Incoming` ones over an async `Transport`.
+///
+/// Received messages are yielded by the [`Stream`](futures_core::Stream) implementation, which
+/// ends when the transport is closed between two messages. A message that can't be read is
+/// reported once, then dropped with [`Connection::skip_to_next_frame`] so the stream carries on
+/// with the next one (without framing, every buffered byte goes with it).
+///
+/// Sent messages are queued by the [`Sink`](futures_sink::Sink) implementation, and only written
+/// once the queue grows past [`DEFAULT_BUFFER_SIZE`](crate::DEFAULT_BUFFER_SIZE) bytes or the
+/// sink is flushed.
+pub struct AsyncConnection<
+    Transport,
+    Protocol,
+    Incoming,
+    Outgoing = Incoming,
+    InBuffer = buffer::RingBuffer,
+    OutBufferFactory = fn() -> Vec<u8>,
+    OutBuffer = Vec<u8>,
+> {
+    transport: Transport,
+    connection: Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>,
+    /// Bytes read from the transport that didn't fit in the input buffer
+    unfed: Vec<u8>,
+    /// Serialized bytes the transport didn't accept yet
+    unsent: Vec<u8>,
+    /// The transport reached its end, nothing more will be read from it
+    eof: bool,
+    /// The stream returned its last item
+    done: bool,
+}
+
+impl<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    AsyncConnection<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+{
+    pub fn new(
+        transport: Transport,
+        connection: Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>,
+    ) -> Self {
+        Self {
+            transport,
+            connection,
+            unfed: Vec::new(),
+            unsent: Vec::new(),
+            eof: false,
+            done: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &Transport {
+        &self.transport
+    }
+
+    /// Reading from (or writing to) the transport directly will desync the connection
+    pub fn get_mut(&mut self) -> &mut Transport {
+        &mut self.transport
+    }
+
+    /// Give back the transport, dropping any buffered bytes
+    pub fn into_inner(self) -> Transport {
+        self.transport
+    }
+}
+
+// Nothing is pinned structurally, only the transport needs to stay put while it is polled
+impl<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer> Unpin
+    for AsyncConnection<
+        Transport,
+        Protocol,
+        Incoming,
+        Outgoing,
+        InBuffer,
+        OutBufferFactory,
+        OutBuffer,
+    >
+where
+    Transport: Unpin,
+{
+}
+
+impl<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    AsyncConnection<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+where
+    Transport: AsyncRead + AsyncWrite + Unpin,
+    Protocol: protocol::Protocol,
+    Incoming: serde::de::DeserializeOwned,
+    Outgoing: serde::Serialize,
+    InBuffer: buffer::Buffer,
+    OutBufferFactory: FnMut() -> OutBuffer,
+    OutBuffer: embedded_io::blocking::Write,
+{
+    /// Feed the connection with more bytes, reading from the transport if needed.
+    ///
+    /// Returns `false` once the transport is closed.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool, Error<Protocol>>> {
+        if self.unfed.is_empty() {
+            if self.eof {
+                return Poll::Ready(Ok(false));
+            }
+            let mut scratch = [0u8; crate::DEFAULT_BUFFER_SIZE];
+            let mut read_buf = ReadBuf::new(&mut scratch);
+            ready!(Pin::new(&mut self.transport).poll_read(cx, &mut read_buf))
+                .map_err(StreamError::Io)?;
+            if read_buf.filled().is_empty() {
+                self.eof = true;
+                return Poll::Ready(Ok(false));
+            }
+            self.unfed.extend_from_slice(read_buf.filled());
+        }
+
+        let fed = self.connection.feed_bytes(&self.unfed);
+        if fed == 0 {
+            return Poll::Ready(Err(StreamError::BufferFull));
+        }
+        self.unfed.drain(..fed);
+        Poll::Ready(Ok(true))
+    }
+
+    /// Write every queued byte to the transport
+    fn poll_write_unsent(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error<Protocol>>> {
+        while !self.unsent.is_empty() {
+            let written = ready!(Pin::new(&mut self.transport).poll_write(cx, &self.unsent))
+                .map_err(StreamError::Io)?;
+            if written == 0 {
+                return Poll::Ready(Err(StreamError::Io(ErrorKind::WriteZero.into())));
+            }
+            self.unsent.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    futures_core::Stream
+    for AsyncConnection<
+        Transport,
+        Protocol,
+        Incoming,
+        Outgoing,
+        InBuffer,
+        OutBufferFactory,
+        OutBuffer,
+    >
+where
+    Transport: AsyncRead + AsyncWrite + Unpin,
+    Protocol: protocol::Protocol,
+    Incoming: serde::de::DeserializeOwned,
+    Outgoing: serde::Serialize,
+    InBuffer: buffer::Buffer,
+    OutBufferFactory: FnMut() -> OutBuffer,
+    OutBuffer: embedded_io::blocking::Write,
+{
+    type Item = Result<Incoming, Error<Protocol>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        if this.done {
+            return Poll::Ready(None);
+        }
+        loop {
+            match this.connection.try_deserialize() {
+                Ok(message) => return Poll::Ready(Some(Ok(message))),
+                Err(DeserializeError::Incomplete { .. }) => {}
+                // A misconfigured connection can't read anything, there is nothing to drop
+                Err(e @ DeserializeError::Unframed) => return Poll::Ready(Some(Err(e.into()))),
+                Err(e) => {
+                    this.connection.skip_to_next_frame();
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+            }
+            match ready!(this.poll_fill(cx)) {
+                Ok(true) => {}
+                Ok(false) => {
+                    this.done = true;
+                    if this.connection.buffered() == 0 {
+                        return Poll::Ready(None);
+                    }
+                    // Report the truncated message once, then end the stream
+                    return Poll::Ready(Some(Err(StreamError::Io(
+                        ErrorKind::UnexpectedEof.into(),
+                    ))));
+                }
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+}
+
+impl<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    futures_sink::Sink<Outgoing>
+    for AsyncConnection<
+        Transport,
+        Protocol,
+        Incoming,
+        Outgoing,
+        InBuffer,
+        OutBufferFactory,
+        OutBuffer,
+    >
+where
+    Transport: AsyncRead + AsyncWrite + Unpin,
+    Protocol: protocol::Protocol,
+    Incoming: serde::de::DeserializeOwned,
+    Outgoing: serde::Serialize,
+    InBuffer: buffer::Buffer,
+    OutBufferFactory: FnMut() -> OutBuffer,
+    OutBuffer: embedded_io::blocking::Write,
+{
+    type Error = Error<Protocol>;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        if this.unsent.len() >= crate::DEFAULT_BUFFER_SIZE {
+            this.poll_write_unsent(cx)
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Outgoing) -> Result<(), Self::Error> {
+        let this = Pin::into_inner(self);
+        // Serialized straight after the queued bytes, a failure must not leave half a message
+        let queued = this.unsent.len();
+        if let Err(e) = this.connection.serialize_into(&item, &mut this.unsent) {
+            this.unsent.truncate(queued);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        ready!(this.poll_write_unsent(cx))?;
+        Pin::new(&mut this.transport)
+            .poll_flush(cx)
+            .map_err(StreamError::Io)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        ready!(this.poll_write_unsent(cx))?;
+        Pin::new(&mut this.transport)
+            .poll_shutdown(cx)
+            .map_err(StreamError::Io)
+    }
+}
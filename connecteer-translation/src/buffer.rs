@@ -1,12 +1,13 @@
 #[cfg(feature = "alloc")]
-pub use with_std::*;
+pub use with_alloc::*;
 
 #[cfg(feature = "alloc")]
-mod with_std {
-    use std::ops::AddAssign;
+mod with_alloc {
+    use super::BufferRead;
+
     pub struct RingBuffer {
         current_bytes_read: usize,
-        inner: std::collections::VecDeque<u8>,
+        inner: alloc::collections::VecDeque<u8>,
     }
 
     impl RingBuffer {
@@ -21,45 +22,38 @@ mod with_std {
         pub fn new(capacity: usize) -> Self {
             Self {
                 current_bytes_read: 0,
-                inner: std::collections::VecDeque::with_capacity(capacity),
+                inner: alloc::collections::VecDeque::with_capacity(capacity),
             }
         }
 
         pub fn as_read(&mut self) -> BufferRead<'_> {
+            let (front, back) = self.inner.as_slices();
             BufferRead {
                 bytes_read: &mut self.current_bytes_read,
-                iter: self.inner.iter(),
+                front,
+                back,
             }
         }
 
         pub fn feed_bytes(&mut self, bytes: &[u8]) {
             self.inner.extend(bytes.iter());
         }
-    }
 
-    pub struct BufferRead<'buf> {
-        bytes_read: &'buf mut usize,
-        iter: std::collections::vec_deque::Iter<'buf, u8>,
-    }
+        pub fn len(&self) -> usize {
+            self.inner.len()
+        }
 
-    impl embedded_io::Io for BufferRead<'_> {
-        type Error = core::convert::Infallible;
-    }
+        pub fn is_empty(&self) -> bool {
+            self.inner.is_empty()
+        }
 
-    impl embedded_io::blocking::Read for BufferRead<'_> {
-        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-            Ok(self
-                .iter
-                .by_ref()
-                .take(buf.len())
-                .zip(buf.iter_mut())
-                .map(|(&r, w)| *w = r)
-                .inspect(|()| self.bytes_read.add_assign(1))
-                .count())
+        /// How many bytes can be held before the buffer needs to grow
+        pub fn capacity(&self) -> usize {
+            self.inner.capacity()
         }
     }
 
-    unsafe impl super::Buffer for RingBuffer {
+    impl super::Buffer for RingBuffer {
         type Reader<'a> = BufferRead<'a>;
 
         fn get_read(&mut self) -> Self::Reader<'_> {
@@ -76,12 +70,174 @@ mod with_std {
         }
 
         fn discard_read_bytes(&mut self) {
+            RingBuffer::discard_read_bytes(self);
             RingBuffer::reset_read_bytes(self)
         }
+
+        fn contiguous(&mut self) -> &mut [u8] {
+            self.inner.make_contiguous()
+        }
+
+        fn len(&self) -> usize {
+            RingBuffer::len(self)
+        }
+
+        fn capacity(&self) -> usize {
+            RingBuffer::capacity(self)
+        }
+    }
+}
+
+/// A fixed capacity ring buffer, usable without an allocator
+pub struct StaticRingBuffer<const N: usize> {
+    current_bytes_read: usize,
+    head: usize,
+    len: usize,
+    inner: [u8; N],
+}
+
+impl<const N: usize> StaticRingBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            current_bytes_read: 0,
+            head: 0,
+            len: 0,
+            inner: [0; N],
+        }
+    }
+
+    pub fn reset_read_bytes(&mut self) {
+        self.current_bytes_read = 0;
+    }
+
+    pub fn discard_read_bytes(&mut self) {
+        let discarded = self.current_bytes_read.min(self.len);
+        self.head = (self.head + discarded) % N.max(1);
+        self.len -= discarded;
+    }
+
+    /// Copy as many bytes as there is free space in the buffer, returning how many were copied
+    pub fn feed_bytes(&mut self, bytes: &[u8]) -> usize {
+        let count = bytes.len().min(N - self.len);
+        let tail = (self.head + self.len) % N.max(1);
+        let first = count.min(N - tail);
+        self.inner[tail..tail + first].copy_from_slice(&bytes[..first]);
+        self.inner[..count - first].copy_from_slice(&bytes[first..count]);
+        self.len += count;
+        count
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Move the buffered bytes to the start of the inner array, and return them
+    pub fn contiguous(&mut self) -> &mut [u8] {
+        if self.head + self.len > N {
+            self.inner.rotate_left(self.head);
+            self.head = 0;
+        }
+        &mut self.inner[self.head..self.head + self.len]
+    }
+
+    pub fn as_read(&mut self) -> BufferRead<'_> {
+        let end = self.head + self.len;
+        let (front, back) = if end <= N {
+            (&self.inner[self.head..end], &self.inner[..0])
+        } else {
+            (&self.inner[self.head..], &self.inner[..end - N])
+        };
+        BufferRead {
+            bytes_read: &mut self.current_bytes_read,
+            front,
+            back,
+        }
     }
 }
 
-pub unsafe trait Buffer {
+impl<const N: usize> Default for StaticRingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads the bytes of a ring buffer, held as the two slices on each side of the wrap point, so
+/// every read is a single copy
+pub struct BufferRead<'buf> {
+    bytes_read: &'buf mut usize,
+    front: &'buf [u8],
+    back: &'buf [u8],
+}
+
+pub type StaticBufferRead<'buf> = BufferRead<'buf>;
+
+impl embedded_io::Io for BufferRead<'_> {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_io::blocking::Read for BufferRead<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.front.is_empty() {
+            self.front = core::mem::take(&mut self.back);
+        }
+        // Deserializers mostly ask for a few bytes at a time, the wrap point is left for the next
+        // read rather than copying from both halves
+        let n = buf.len().min(self.front.len());
+        let (read, rest) = self.front.split_at(n);
+        if let ([byte], [w, ..]) = (read, &mut *buf) {
+            // Text deserializers read one byte at a time, which isn't worth a call to memcpy
+            *w = *byte;
+        } else {
+            buf[..n].copy_from_slice(read);
+        }
+        self.front = rest;
+        *self.bytes_read += n;
+        Ok(n)
+    }
+}
+
+impl<const N: usize> Buffer for StaticRingBuffer<N> {
+    type Reader<'a> = BufferRead<'a>;
+
+    fn get_read(&mut self) -> Self::Reader<'_> {
+        self.as_read()
+    }
+
+    fn feed_bytes(&mut self, bytes: &[u8]) -> usize {
+        StaticRingBuffer::feed_bytes(self, bytes)
+    }
+
+    fn keep_read_bytes(&mut self) {
+        StaticRingBuffer::reset_read_bytes(self)
+    }
+
+    fn discard_read_bytes(&mut self) {
+        StaticRingBuffer::discard_read_bytes(self);
+        StaticRingBuffer::reset_read_bytes(self)
+    }
+
+    fn contiguous(&mut self) -> &mut [u8] {
+        StaticRingBuffer::contiguous(self)
+    }
+
+    fn len(&self) -> usize {
+        StaticRingBuffer::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        StaticRingBuffer::capacity(self)
+    }
+}
+
+pub trait Buffer {
     type Reader<'a>: embedded_io::blocking::Read<Error = core::convert::Infallible> + 'a
     where
         Self: 'a;
@@ -95,8 +251,20 @@ pub unsafe trait Buffer {
     /// discarding these bytes (or resetting read head)
     fn get_read(&mut self) -> Self::Reader<'_>;
     /// Discard every bytes that were read from an `Self::Reader`
+    /// The next Reader will start right after the discarded bytes
     fn discard_read_bytes(&mut self);
     /// Keep the bytes that were read into the buffer, allowing them to be re-read when a new
     /// Reader is reading into the buffer
     fn keep_read_bytes(&mut self);
+    /// Get every buffered byte as a single slice, moving them around if needed, without reading
+    /// (nor discarding) any of them
+    fn contiguous(&mut self) -> &mut [u8];
+    /// The number of buffered bytes, including the ones read but not discarded yet
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// The number of bytes the buffer can hold without growing (or refusing bytes, for a fixed
+    /// size buffer)
+    fn capacity(&self) -> usize;
 }
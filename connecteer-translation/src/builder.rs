@@ -0,0 +1,242 @@
+use crate::{
+    buffer, envelope::Envelope, protocol, Checksum, Compression, Connection, Framing, Limits,
+};
+
+/// Placeholder for a part of a [`ConnectionBuilder`] that hasn't been chosen yet
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unset;
+
+#[cfg(feature = "alloc")]
+type DefaultInBuffer = buffer::RingBuffer;
+#[cfg(not(feature = "alloc"))]
+type DefaultInBuffer = Unset;
+
+#[cfg(feature = "alloc")]
+type DefaultOutBufferFactory = fn() -> alloc::vec::Vec<u8>;
+#[cfg(not(feature = "alloc"))]
+type DefaultOutBufferFactory = Unset;
+
+/// Builds a [`Connection`] one part at a time.
+///
+/// With the `alloc` feature, the input buffer defaults to a [`RingBuffer`](buffer::RingBuffer)
+/// of [`DEFAULT_BUFFER_SIZE`](crate::DEFAULT_BUFFER_SIZE) bytes and messages are serialized into
+/// new `Vec<u8>`s. Without it, both buffers need to be set before calling
+/// [`ConnectionBuilder::build`].
+///
+/// The protocol always has to be set, with [`ConnectionBuilder::protocol`]. The message types are
+/// set with either [`ConnectionBuilder::message`] or both [`ConnectionBuilder::incoming`] and
+/// [`ConnectionBuilder::outgoing`], and are only required to be (de)serializable by the methods
+/// sending and receiving them: a connection only receiving messages that borrow from its buffer
+/// can be built too.
+pub struct ConnectionBuilder<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory> {
+    protocol: Protocol,
+    framing: Framing,
+    envelope: Envelope,
+    limits: Limits,
+    in_buffer: InBuffer,
+    out_buffer_factory: OutBufferFactory,
+    message_marker: core::marker::PhantomData<fn() -> (Incoming, Outgoing)>,
+}
+
+impl ConnectionBuilder<Unset, Unset, Unset, DefaultInBuffer, DefaultOutBufferFactory> {
+    pub fn new() -> Self {
+        Self {
+            protocol: Unset,
+            framing: Framing::None,
+            envelope: Envelope::new(),
+            limits: Limits::default(),
+            #[cfg(feature = "alloc")]
+            in_buffer: buffer::RingBuffer::new(crate::DEFAULT_BUFFER_SIZE),
+            #[cfg(not(feature = "alloc"))]
+            in_buffer: Unset,
+            #[cfg(feature = "alloc")]
+            out_buffer_factory: alloc::vec::Vec::new,
+            #[cfg(not(feature = "alloc"))]
+            out_buffer_factory: Unset,
+            message_marker: core::marker::PhantomData,
+        }
+    }
+}
+
+impl Default for ConnectionBuilder<Unset, Unset, Unset, DefaultInBuffer, DefaultOutBufferFactory> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory>
+    ConnectionBuilder<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory>
+{
+    /// Set the serde format used on the connection
+    pub fn protocol<P: protocol::Protocol>(
+        self,
+        protocol: P,
+    ) -> ConnectionBuilder<P, Incoming, Outgoing, InBuffer, OutBufferFactory> {
+        ConnectionBuilder {
+            protocol,
+            framing: self.framing,
+            envelope: self.envelope,
+            limits: self.limits,
+            in_buffer: self.in_buffer,
+            out_buffer_factory: self.out_buffer_factory,
+            message_marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Set the type of the messages sent and received on the connection
+    pub fn message<M>(self) -> ConnectionBuilder<Protocol, M, M, InBuffer, OutBufferFactory>
+    where
+        M: serde::Serialize,
+    {
+        self.incoming().outgoing()
+    }
+
+    /// Set the type of the messages received on the connection
+    pub fn incoming<I>(
+        self,
+    ) -> ConnectionBuilder<Protocol, I, Outgoing, InBuffer, OutBufferFactory> {
+        ConnectionBuilder {
+            protocol: self.protocol,
+            framing: self.framing,
+            envelope: self.envelope,
+            limits: self.limits,
+            in_buffer: self.in_buffer,
+            out_buffer_factory: self.out_buffer_factory,
+            message_marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Set the type of the messages sent on the connection
+    pub fn outgoing<O>(self) -> ConnectionBuilder<Protocol, Incoming, O, InBuffer, OutBufferFactory>
+    where
+        O: serde::Serialize,
+    {
+        ConnectionBuilder {
+            protocol: self.protocol,
+            framing: self.framing,
+            envelope: self.envelope,
+            limits: self.limits,
+            in_buffer: self.in_buffer,
+            out_buffer_factory: self.out_buffer_factory,
+            message_marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Set how messages are delimited on the byte stream
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Follow every message with a checksum, checked when it is deserialized
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.envelope.checksum = Some(checksum);
+        self
+    }
+
+    /// Compress the serialized messages, which requires a length prefix or COBS framing: with any
+    /// other framing, messages are refused as
+    /// [`DeserializeError::Unframed`](crate::DeserializeError::Unframed)
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.envelope.compression = Some(compression);
+        self
+    }
+
+    /// Encrypt and authenticate every message, which requires a length prefix or COBS framing:
+    /// with any other framing, messages are refused as
+    /// [`DeserializeError::Unframed`](crate::DeserializeError::Unframed)
+    #[cfg(feature = "chacha20poly1305")]
+    pub fn encryption(mut self, encryption: crate::Encryption) -> Self {
+        self.envelope.encryption = Some(encryption);
+        self
+    }
+
+    /// Bound the number of bytes buffered, and the size of a single message
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Use `buffer` to hold the received bytes
+    pub fn in_buffer<B: buffer::Buffer>(
+        self,
+        buffer: B,
+    ) -> ConnectionBuilder<Protocol, Incoming, Outgoing, B, OutBufferFactory> {
+        ConnectionBuilder {
+            protocol: self.protocol,
+            framing: self.framing,
+            envelope: self.envelope,
+            limits: self.limits,
+            in_buffer: buffer,
+            out_buffer_factory: self.out_buffer_factory,
+            message_marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Hold the received bytes in a [`RingBuffer`](buffer::RingBuffer) that starts with
+    /// `capacity` bytes
+    #[cfg(feature = "alloc")]
+    pub fn ring_buffer(
+        self,
+        capacity: usize,
+    ) -> ConnectionBuilder<Protocol, Incoming, Outgoing, buffer::RingBuffer, OutBufferFactory> {
+        self.in_buffer(buffer::RingBuffer::new(capacity))
+    }
+
+    /// Hold the received bytes in a [`StaticRingBuffer`](buffer::StaticRingBuffer) of `N` bytes
+    pub fn static_ring_buffer<const N: usize>(
+        self,
+    ) -> ConnectionBuilder<
+        Protocol,
+        Incoming,
+        Outgoing,
+        buffer::StaticRingBuffer<N>,
+        OutBufferFactory,
+    > {
+        self.in_buffer(buffer::StaticRingBuffer::new())
+    }
+
+    /// Serialize every message into a new buffer returned by `factory`
+    pub fn out_buffer<F, O>(
+        self,
+        factory: F,
+    ) -> ConnectionBuilder<Protocol, Incoming, Outgoing, InBuffer, F>
+    where
+        F: FnMut() -> O,
+        O: embedded_io::blocking::Write,
+    {
+        ConnectionBuilder {
+            protocol: self.protocol,
+            framing: self.framing,
+            envelope: self.envelope,
+            limits: self.limits,
+            in_buffer: self.in_buffer,
+            out_buffer_factory: factory,
+            message_marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Serialize every message into a new `Vec<u8>`
+    #[cfg(feature = "alloc")]
+    pub fn vec_out_buffer(
+        self,
+    ) -> ConnectionBuilder<Protocol, Incoming, Outgoing, InBuffer, DefaultOutBufferFactory> {
+        self.out_buffer(alloc::vec::Vec::new)
+    }
+
+    pub fn build<OutBuffer>(
+        self,
+    ) -> Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    where
+        Protocol: protocol::Protocol,
+        InBuffer: buffer::Buffer,
+        OutBufferFactory: FnMut() -> OutBuffer,
+    {
+        let mut connection =
+            Connection::new(self.protocol, self.out_buffer_factory, self.in_buffer)
+                .with_framing(self.framing)
+                .with_limits(self.limits);
+        connection.envelope = self.envelope;
+        connection
+    }
+}
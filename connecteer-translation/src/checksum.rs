@@ -0,0 +1,138 @@
+//! Integrity checks for the messages of a [`Connection`](crate::Connection).
+//!
+//! A checksum catches a message that was corrupted on the way but still deserializes, like a
+//! bit flipped in a number over a noisy serial link.
+
+/// A checksum computed over every serialized message, and sent right after it as a trailer.
+///
+/// The trailer is part of the message for the [`Framing`](crate::Framing): it is inside the
+/// length prefix or the COBS frame. It is written in big endian, except with
+/// [`Framing::Lines`](crate::Framing::Lines) where it is written as lowercase hexadecimal digits,
+/// so it can't hold a newline.
+///
+/// A message whose trailer doesn't match is reported as
+/// [`DeserializeError::ChecksumMismatch`](crate::DeserializeError::ChecksumMismatch), and can be
+/// dropped like an invalid one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC-16/CCITT-FALSE, a 2 bytes trailer
+    Crc16,
+    /// CRC-32 as used by Ethernet and zlib (CRC-32/ISO-HDLC), a 4 bytes trailer
+    Crc32,
+}
+
+impl Checksum {
+    /// The number of bytes of the trailer
+    pub const fn trailer_len(self) -> usize {
+        match self {
+            Checksum::Crc16 => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    /// The number of bytes of the trailer, once written as hexadecimal digits if `hex` is set
+    pub(crate) const fn encoded_len(self, hex: bool) -> usize {
+        if hex {
+            self.trailer_len() * 2
+        } else {
+            self.trailer_len()
+        }
+    }
+
+    /// The checksum of `bytes`
+    pub fn compute(self, bytes: &[u8]) -> u32 {
+        let mut crc = Crc::new(self);
+        crc.update(bytes);
+        crc.finish()
+    }
+
+    /// Write the trailer for `crc`
+    pub(crate) fn write_trailer<W>(self, crc: u32, hex: bool, mut writer: W) -> Result<(), W::Error>
+    where
+        W: embedded_io::blocking::Write,
+    {
+        let bytes = crc.to_be_bytes();
+        let bytes = &bytes[4 - self.trailer_len()..];
+        if !hex {
+            return writer.write_all(bytes);
+        }
+        let mut digits = [0u8; 8];
+        for (pair, byte) in digits.chunks_exact_mut(2).zip(bytes) {
+            pair[0] = HEX_DIGITS[usize::from(byte >> 4)];
+            pair[1] = HEX_DIGITS[usize::from(byte & 0xf)];
+        }
+        writer.write_all(&digits[..bytes.len() * 2])
+    }
+
+    /// Check the trailer at the end of `bytes`, returning the message before it if it matches
+    pub(crate) fn verify(self, bytes: &[u8], hex: bool) -> Option<&[u8]> {
+        let split = bytes.len().checked_sub(self.encoded_len(hex))?;
+        let (message, trailer) = bytes.split_at(split);
+        let expected = if hex {
+            trailer.iter().try_fold(0u32, |crc, &digit| {
+                let value = HEX_DIGITS.iter().position(|&d| d == digit)?;
+                Some(crc << 4 | value as u32)
+            })?
+        } else {
+            trailer
+                .iter()
+                .fold(0u32, |crc, &byte| crc << 8 | u32::from(byte))
+        };
+        (self.compute(message) == expected).then_some(message)
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// A checksum being computed over bytes coming in pieces
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Crc {
+    checksum: Checksum,
+    state: u32,
+}
+
+impl Crc {
+    pub(crate) fn new(checksum: Checksum) -> Self {
+        let state = match checksum {
+            Checksum::Crc16 => 0xffff,
+            Checksum::Crc32 => 0xffff_ffff,
+        };
+        Self { checksum, state }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self.checksum {
+            Checksum::Crc16 => {
+                for &byte in bytes {
+                    self.state ^= u32::from(byte) << 8;
+                    for _ in 0..8 {
+                        self.state = if self.state & 0x8000 != 0 {
+                            (self.state << 1) ^ 0x1021
+                        } else {
+                            self.state << 1
+                        } & 0xffff;
+                    }
+                }
+            }
+            Checksum::Crc32 => {
+                for &byte in bytes {
+                    self.state ^= u32::from(byte);
+                    for _ in 0..8 {
+                        self.state = if self.state & 1 != 0 {
+                            (self.state >> 1) ^ 0xedb8_8320
+                        } else {
+                            self.state >> 1
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> u32 {
+        match self.checksum {
+            Checksum::Crc16 => self.state,
+            Checksum::Crc32 => !self.state,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Compression of the serialized messages of a [`Connection`](crate::Connection), with the codecs
+//! enabled by cargo features.
+//!
+//! Every message starts with a flag byte telling whether the rest of it is compressed: `0` when
+//! it isn't, `1` for LZ4. Compressed bytes can hold anything, so messages need a
+//! [`Framing::LengthPrefix`](crate::Framing::LengthPrefix) or a
+//! [`Framing::Cobs`](crate::Framing::Cobs). A [`Checksum`](crate::Checksum) covers the message as
+//! it is sent, flag byte included.
+
+use crate::{DeserializeError, Limits};
+
+#[cfg(feature = "lz4")]
+const UNCOMPRESSED: u8 = 0;
+#[cfg(feature = "lz4")]
+const LZ4: u8 = 1;
+
+/// How the serialized messages are compressed.
+///
+/// Without any codec feature enabled, this has no variant, and messages can't be compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// LZ4 block compression, for messages of at least `threshold` bytes once serialized.
+    ///
+    /// Smaller messages, and those LZ4 can't make smaller, are sent as they are.
+    #[cfg(feature = "lz4")]
+    Lz4 { threshold: usize },
+}
+
+impl Compression {
+    /// Turn the `serialized` message into what is sent, flag byte included
+    #[cfg(feature = "lz4")]
+    pub(crate) fn pack(self, serialized: &[u8]) -> alloc::vec::Vec<u8> {
+        match self {
+            Compression::Lz4 { threshold } => {
+                if serialized.len() >= threshold {
+                    let compressed = lz4_flex::block::compress_prepend_size(serialized);
+                    if compressed.len() < serialized.len() {
+                        let mut packed = alloc::vec::Vec::with_capacity(compressed.len() + 1);
+                        packed.push(LZ4);
+                        packed.extend_from_slice(&compressed);
+                        return packed;
+                    }
+                }
+                let mut packed = alloc::vec::Vec::with_capacity(serialized.len() + 1);
+                packed.push(UNCOMPRESSED);
+                packed.extend_from_slice(serialized);
+                packed
+            }
+        }
+    }
+
+    /// Run `deserialize` over the serialized message held by `packed`, decompressing it first if
+    /// needed.
+    ///
+    /// A message that would decompress past [`Limits::max_message`] is reported as
+    /// [`DeserializeError::Oversized`], before anything is allocated for it.
+    pub(crate) fn unpack<T, E>(
+        compression: Option<Self>,
+        limits: &Limits,
+        packed: &[u8],
+        deserialize: impl FnOnce(&[u8]) -> Result<T, DeserializeError<E>>,
+    ) -> Result<T, DeserializeError<E>>
+    where
+        E: serde::de::Error,
+    {
+        // Without a codec, nothing is decompressed
+        #[cfg(not(feature = "lz4"))]
+        let _ = limits;
+        let Some(compression) = compression else {
+            return deserialize(packed);
+        };
+        match compression {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 { .. } => match packed.split_first() {
+                Some((&UNCOMPRESSED, serialized)) => deserialize(serialized),
+                Some((&LZ4, compressed)) => {
+                    let (len, block) = lz4_flex::block::uncompressed_size(compressed)
+                        .map_err(|_| invalid("missing LZ4 length"))?;
+                    // LZ4 can't expand a block more than 255 times, a larger length is bogus
+                    if len > block.len().saturating_mul(255) {
+                        return Err(invalid("invalid LZ4 length"));
+                    }
+                    if limits.too_long(len) {
+                        return Err(DeserializeError::Oversized);
+                    }
+                    let serialized = lz4_flex::block::decompress(block, len)
+                        .map_err(|_| invalid("invalid LZ4 block"))?;
+                    deserialize(&serialized)
+                }
+                _ => Err(invalid("unknown compression flag")),
+            },
+        }
+    }
+
+    /// The serialized message held by `packed`, which has to be left uncompressed since it is
+    /// borrowed from
+    pub(crate) fn uncompressed<E>(
+        compression: Option<Self>,
+        packed: &[u8],
+    ) -> Result<&[u8], DeserializeError<E>>
+    where
+        E: serde::de::Error,
+    {
+        let Some(compression) = compression else {
+            return Ok(packed);
+        };
+        match compression {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 { .. } => match packed.split_first() {
+                Some((&UNCOMPRESSED, serialized)) => Ok(serialized),
+                Some((&LZ4, _)) => Err(invalid("compressed messages can't be borrowed")),
+                _ => Err(invalid("unknown compression flag")),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "lz4")]
+fn invalid<E: serde::de::Error>(msg: &'static str) -> DeserializeError<E> {
+    DeserializeError::Invalid(E::custom(msg))
+}
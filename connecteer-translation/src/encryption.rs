@@ -0,0 +1,142 @@
+//! Authenticated encryption of the messages of a [`Connection`](crate::Connection), with
+//! ChaCha20-Poly1305.
+//!
+//! Every message is sent as a 12 bytes nonce, the encrypted message, and a 16 bytes tag. The
+//! nonce is made of the [`Role`] of the sender and of a counter, so both ends can share a single
+//! key without ever using the same nonce twice. Messages are only handed to the deserializer once
+//! they are authenticated, and a message whose counter isn't past the last one received (a
+//! replayed or reflected message) is refused.
+//!
+//! Encrypted bytes can hold anything, so messages need a
+//! [`Framing::LengthPrefix`](crate::Framing::LengthPrefix) or a
+//! [`Framing::Cobs`](crate::Framing::Cobs). Messages are compressed before being encrypted, and a
+//! [`Checksum`](crate::Checksum) covers the encrypted message.
+
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, KeyInit, Nonce, Tag};
+
+use crate::DeserializeError;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Which end of the connection a message comes from, the two ends must have different roles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Initiator,
+    Responder,
+}
+
+impl Role {
+    fn peer(self) -> Self {
+        match self {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        }
+    }
+
+    fn tag(self) -> [u8; 4] {
+        match self {
+            Role::Initiator => [0; 4],
+            Role::Responder => [0, 0, 0, 1],
+        }
+    }
+}
+
+/// The key and nonce counters used to encrypt messages, for one end of the connection.
+///
+/// This is deliberately not `Clone`: two copies would send messages with the same nonces.
+pub struct Encryption {
+    cipher: ChaCha20Poly1305,
+    role: Role,
+    /// The counter of the next message sent
+    sent: u64,
+    /// The lowest counter still accepted from the peer
+    received: u64,
+}
+
+impl Encryption {
+    /// Encrypt with `key`, shared by both ends
+    pub fn new(key: &[u8; 32], role: Role) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(key.into()),
+            role,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// The number of bytes added to every message
+    pub(crate) const fn overhead() -> usize {
+        NONCE_LEN + TAG_LEN
+    }
+
+    /// Encrypt `message`, returning the bytes to send
+    pub(crate) fn seal<E>(&mut self, message: &[u8]) -> Result<alloc::vec::Vec<u8>, E>
+    where
+        E: serde::ser::Error,
+    {
+        let counter = self.sent;
+        self.sent = counter
+            .checked_add(1)
+            .ok_or_else(|| E::custom("no nonce left for this key"))?;
+        let nonce = nonce(self.role, counter);
+
+        let mut sealed = alloc::vec::Vec::with_capacity(message.len() + Self::overhead());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(message);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), &[], &mut sealed[NONCE_LEN..])
+            .map_err(|_| E::custom("message too long to be encrypted"))?;
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    /// Authenticate and decrypt `sealed` in place, returning the message.
+    ///
+    /// `sealed` is left untouched when the message is refused.
+    pub(crate) fn open<'a, E>(
+        &mut self,
+        sealed: &'a mut [u8],
+    ) -> Result<&'a [u8], DeserializeError<E>> {
+        if sealed.len() < Self::overhead() {
+            return Err(DeserializeError::Unauthenticated);
+        }
+        let (nonce, rest) = sealed.split_at_mut(NONCE_LEN);
+        let (message, tag) = rest.split_at_mut(rest.len() - TAG_LEN);
+
+        let (role, counter) = nonce.split_at(4);
+        let counter = u64::from_be_bytes(counter.try_into().unwrap());
+        // `u64::MAX` is never sent, see `seal`
+        if role != self.role.peer().tag() || counter < self.received || counter == u64::MAX {
+            return Err(DeserializeError::Unauthenticated);
+        }
+        self.cipher
+            .decrypt_in_place_detached(Nonce::from_slice(nonce), &[], message, Tag::from_slice(tag))
+            .map_err(|_| DeserializeError::Unauthenticated)?;
+        self.received = counter + 1;
+        Ok(message)
+    }
+}
+
+impl core::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // The key stays out of the logs
+        f.debug_struct("Encryption")
+            .field("role", &self.role)
+            .field("sent", &self.sent)
+            .field("received", &self.received)
+            .finish_non_exhaustive()
+    }
+}
+
+fn nonce(role: Role, counter: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..4].copy_from_slice(&role.tag());
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
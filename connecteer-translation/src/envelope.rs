@@ -0,0 +1,90 @@
+//! The layers wrapped around every serialized message, inside its frame: compression, then
+//! encryption, then a checksum trailer
+
+use crate::{Checksum, Compression, DeserializeError};
+
+#[derive(Debug)]
+pub(crate) struct Envelope {
+    pub(crate) checksum: Option<Checksum>,
+    pub(crate) compression: Option<Compression>,
+    #[cfg(feature = "chacha20poly1305")]
+    pub(crate) encryption: Option<crate::encryption::Encryption>,
+}
+
+impl Envelope {
+    pub(crate) const fn new() -> Self {
+        Self {
+            checksum: None,
+            compression: None,
+            #[cfg(feature = "chacha20poly1305")]
+            encryption: None,
+        }
+    }
+
+    /// Whether messages are turned into arbitrary bytes, which only a length prefix or COBS can
+    /// delimit
+    pub(crate) fn is_binary(&self) -> bool {
+        #[cfg(feature = "chacha20poly1305")]
+        if self.encryption.is_some() {
+            return true;
+        }
+        self.compression.is_some()
+    }
+
+    /// Whether the frame holds anything else than the serialized message
+    pub(crate) fn is_empty(&self) -> bool {
+        self.checksum.is_none() && !self.is_binary()
+    }
+
+    /// Compress and encrypt the `serialized` message, when [`Envelope::is_binary`]
+    #[cfg(feature = "alloc")]
+    pub(crate) fn pack<E>(
+        &mut self,
+        serialized: alloc::vec::Vec<u8>,
+    ) -> Result<alloc::vec::Vec<u8>, E>
+    where
+        E: serde::ser::Error,
+    {
+        #[cfg(feature = "lz4")]
+        let serialized = match self.compression {
+            Some(compression) => compression.pack(&serialized),
+            None => serialized,
+        };
+        #[cfg(feature = "chacha20poly1305")]
+        let serialized = match &mut self.encryption {
+            Some(encryption) => encryption.seal(&serialized)?,
+            None => serialized,
+        };
+        Ok(serialized)
+    }
+
+    /// Check the checksum trailer of the message in `frame` and decrypt it in place, returning
+    /// what is left for [`Compression::unpack`]
+    pub(crate) fn open<'a, E>(
+        &mut self,
+        frame: &'a mut [u8],
+        hex: bool,
+    ) -> Result<&'a [u8], DeserializeError<E>> {
+        let len = verify_trailer(self.checksum, frame, hex)?.len();
+        #[cfg(feature = "chacha20poly1305")]
+        if let Some(encryption) = &mut self.encryption {
+            return encryption.open(&mut frame[..len]);
+        }
+        Ok(&frame[..len])
+    }
+}
+
+/// Check the checksum trailer at the end of `bytes`, if there is one, returning the message before
+/// it
+pub(crate) fn verify_trailer<E>(
+    checksum: Option<Checksum>,
+    bytes: &[u8],
+    hex: bool,
+) -> Result<&[u8], DeserializeError<E>> {
+    match checksum {
+        Some(checksum) => checksum
+            .verify(bytes, hex)
+            .ok_or(DeserializeError::ChecksumMismatch),
+        None => Ok(bytes),
+    }
+}
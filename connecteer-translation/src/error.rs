@@ -0,0 +1,158 @@
+//! A single error type for everything that can go wrong while sending or receiving messages.
+//!
+//! [`DeserializeError`], [`SerializeError`] and the errors of the connections built on top of a
+//! [`Connection`](crate::Connection) all convert into an [`Error`], so `?` can be used across
+//! them. The errors of the serializer, the deserializer and the transport are kept as its
+//! [`source`](core::error::Error::source), and their message is included in its own.
+
+use core::convert::Infallible;
+use core::fmt;
+
+use crate::{DeserializeError, FramingError, Overflow, SerializeError};
+
+/// The reason why a message couldn't be sent or received
+#[derive(Debug)]
+pub enum Error<SerializerError, DeserializerError> {
+    /// The message couldn't be serialized
+    Serialize(SerializerError),
+    /// The received bytes aren't a valid message
+    Deserialize(DeserializerError),
+    /// The received bytes can't be split into frames
+    Framing(FramingError),
+    /// The buffer doesn't hold a whole message yet, see [`DeserializeError::Incomplete`]
+    Incomplete { needed: Option<usize> },
+    /// The input buffer is full and still doesn't hold a whole message, or the output slice is
+    /// too small for the message
+    BufferFull,
+    /// The message doesn't fit in the [`Limits`](crate::Limits) of the connection
+    Oversized,
+    /// The [`Checksum`](crate::Checksum) of the received message doesn't match, it can be dropped
+    /// with `skip_to_next_frame`
+    ChecksumMismatch,
+    /// The received message failed authentication, it can be dropped with `skip_to_next_frame`
+    Unauthenticated,
+    /// Compression or encryption is enabled without a framing able to delimit their bytes, see
+    /// [`DeserializeError::Unframed`]
+    Unframed,
+    /// The transport returned an error.
+    ///
+    /// [`ErrorKind::WouldBlock`](std::io::ErrorKind::WouldBlock) is only a pause: nothing is
+    /// lost, and the call can be retried once the transport is ready. An end of stream in the
+    /// middle of a message is reported as
+    /// [`ErrorKind::UnexpectedEof`](std::io::ErrorKind::UnexpectedEof).
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    /// The transport reached its end between two messages
+    Closed,
+}
+
+impl<SerializerError, DeserializerError> fmt::Display for Error<SerializerError, DeserializerError>
+where
+    SerializerError: fmt::Display,
+    DeserializerError: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Serialize(e) => write!(f, "the message couldn't be serialized: {e}"),
+            Error::Deserialize(e) => write!(f, "the received message is invalid: {e}"),
+            Error::Framing(e) => write!(f, "the received bytes aren't a valid frame: {e}"),
+            Error::Incomplete {
+                needed: Some(needed),
+            } => {
+                write!(f, "the message is missing at least {needed} bytes")
+            }
+            Error::Incomplete { needed: None } => f.write_str("the message is incomplete"),
+            Error::BufferFull => f.write_str("the buffer is full"),
+            Error::Oversized => f.write_str("the message is larger than the limits"),
+            Error::ChecksumMismatch => f.write_str("checksum mismatch"),
+            Error::Unauthenticated => f.write_str("the message failed authentication"),
+            Error::Unframed => {
+                f.write_str("compressed or encrypted messages need a length prefix or COBS framing")
+            }
+            #[cfg(feature = "std")]
+            Error::Io(e) => write!(f, "the transport failed: {e}"),
+            Error::Closed => f.write_str("the transport is closed"),
+        }
+    }
+}
+
+impl<SerializerError, DeserializerError> core::error::Error
+    for Error<SerializerError, DeserializerError>
+where
+    SerializerError: core::error::Error + 'static,
+    DeserializerError: core::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Serialize(e) => Some(e),
+            Error::Deserialize(e) => Some(e),
+            Error::Framing(e) => Some(e),
+            #[cfg(feature = "std")]
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl<SerializerError, DeserializerError> From<DeserializeError<DeserializerError>>
+    for Error<SerializerError, DeserializerError>
+{
+    fn from(value: DeserializeError<DeserializerError>) -> Self {
+        match value {
+            DeserializeError::Incomplete { needed } => Error::Incomplete { needed },
+            DeserializeError::Invalid(e) => Error::Deserialize(e),
+            DeserializeError::Framing(e) => Error::Framing(e),
+            DeserializeError::Oversized => Error::Oversized,
+            DeserializeError::ChecksumMismatch => Error::ChecksumMismatch,
+            DeserializeError::Unauthenticated => Error::Unauthenticated,
+            DeserializeError::Unframed => Error::Unframed,
+        }
+    }
+}
+
+/// Writing into a `Vec` can't fail
+impl<SerializerError, DeserializerError> From<SerializeError<SerializerError, Infallible>>
+    for Error<SerializerError, DeserializerError>
+{
+    fn from(value: SerializeError<SerializerError, Infallible>) -> Self {
+        match value {
+            SerializeError::Serialize(e) => Error::Serialize(e),
+            SerializeError::Write(e) => match e {},
+            SerializeError::Unframed => Error::Unframed,
+        }
+    }
+}
+
+impl<SerializerError, DeserializerError> From<SerializeError<SerializerError, Overflow>>
+    for Error<SerializerError, DeserializerError>
+{
+    fn from(value: SerializeError<SerializerError, Overflow>) -> Self {
+        match value {
+            SerializeError::Serialize(e) => Error::Serialize(e),
+            SerializeError::Write(Overflow) => Error::BufferFull,
+            SerializeError::Unframed => Error::Unframed,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<SerializerError, DeserializerError> From<SerializeError<SerializerError, std::io::Error>>
+    for Error<SerializerError, DeserializerError>
+{
+    fn from(value: SerializeError<SerializerError, std::io::Error>) -> Self {
+        match value {
+            SerializeError::Serialize(e) => Error::Serialize(e),
+            SerializeError::Write(e) => Error::Io(e),
+            SerializeError::Unframed => Error::Unframed,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<SerializerError, DeserializerError> From<std::io::Error>
+    for Error<SerializerError, DeserializerError>
+{
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
@@ -0,0 +1,207 @@
+/// How messages are delimited when they are written to (or read from) a byte stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// Messages are written back to back, and the deserializer is the one finding where a message
+    /// ends.
+    ///
+    /// This only works with self-delimiting formats (like JSON objects or MessagePack), and an
+    /// incomplete message can't be told apart from a malformed one.
+    #[default]
+    None,
+    /// Every message is preceded by the length of its serialized form
+    LengthPrefix(LengthPrefix),
+    /// Every message is encoded with Consistent Overhead Byte Stuffing, which removes every zero
+    /// byte from it, and followed by a zero byte.
+    ///
+    /// The next frame can always be found, so a corrupt byte only loses the message it is part of:
+    /// a frame that can't be decoded (or deserialized) is dropped as soon as it is reported.
+    /// Frames are decoded in place, inside the input buffer.
+    Cobs,
+    /// Every message is followed by a newline, like JSON Lines.
+    ///
+    /// This only works with formats that never write a newline inside a message, like JSON (which
+    /// escapes them in strings). A message is only parsed once its newline is received, and
+    /// [`Connection::skip_to_next_frame`](crate::Connection::skip_to_next_frame) drops a bad line
+    /// without touching the next ones. Empty lines are skipped, and a line holding anything but
+    /// its message and whitespace is invalid.
+    Lines,
+}
+
+/// The encoding of the length written in front of a message by [`Framing::LengthPrefix`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPrefix {
+    /// LEB128 encoded unsigned integer, takes between 1 and [`LengthPrefix::MAX_HEADER_LEN`] bytes
+    Varint,
+    /// Big endian `u16`
+    U16Be,
+    /// Little endian `u16`
+    U16Le,
+    /// Big endian `u32`
+    U32Be,
+    /// Little endian `u32`
+    U32Le,
+}
+
+/// The reason why the buffered bytes can't be split into frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FramingError {
+    /// The bytes at the start of the buffer can't be a [`LengthPrefix`]
+    InvalidLengthPrefix,
+    /// A code byte of a [`Framing::Cobs`] frame points past the end of the frame
+    InvalidCobs,
+}
+
+impl core::fmt::Display for FramingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            FramingError::InvalidLengthPrefix => "invalid length prefix",
+            FramingError::InvalidCobs => "invalid COBS frame",
+        })
+    }
+}
+
+impl core::error::Error for FramingError {}
+
+/// The result of trying to read a length prefix out of the buffered bytes
+pub(crate) enum Header {
+    /// The header has been read: the payload is `len` bytes long, and the header itself took
+    /// `header_len` bytes
+    Complete { len: usize, header_len: usize },
+    /// There isn't enough bytes to read the whole header
+    Incomplete { needed: Option<usize> },
+    /// The bytes can't be a valid header
+    Invalid,
+}
+
+impl LengthPrefix {
+    /// The maximum number of bytes a length prefix can take
+    pub const MAX_HEADER_LEN: usize = 10;
+
+    /// Encode `len` into `out`, returning the number of bytes used.
+    ///
+    /// Returns `None` if `len` can't be represented by this prefix
+    pub fn encode(self, len: usize, out: &mut [u8; Self::MAX_HEADER_LEN]) -> Option<usize> {
+        match self {
+            LengthPrefix::Varint => {
+                let mut len = len as u64;
+                let mut i = 0;
+                loop {
+                    let byte = (len & 0x7F) as u8;
+                    len >>= 7;
+                    if len == 0 {
+                        out[i] = byte;
+                        break Some(i + 1);
+                    }
+                    out[i] = byte | 0x80;
+                    i += 1;
+                }
+            }
+            LengthPrefix::U16Be => {
+                out[..2].copy_from_slice(&u16::try_from(len).ok()?.to_be_bytes());
+                Some(2)
+            }
+            LengthPrefix::U16Le => {
+                out[..2].copy_from_slice(&u16::try_from(len).ok()?.to_le_bytes());
+                Some(2)
+            }
+            LengthPrefix::U32Be => {
+                out[..4].copy_from_slice(&u32::try_from(len).ok()?.to_be_bytes());
+                Some(4)
+            }
+            LengthPrefix::U32Le => {
+                out[..4].copy_from_slice(&u32::try_from(len).ok()?.to_le_bytes());
+                Some(4)
+            }
+        }
+    }
+
+    /// Read a length prefix from `reader`, one byte at a time so nothing past the header is read
+    pub(crate) fn read_header<R>(self, reader: &mut R) -> Header
+    where
+        R: embedded_io::blocking::Read<Error = core::convert::Infallible> + ?Sized,
+    {
+        let mut next_byte = || {
+            let mut byte = [0u8];
+            match reader.read(&mut byte) {
+                Ok(0) => None,
+                Ok(_) => Some(byte[0]),
+                Err(e) => match e {},
+            }
+        };
+
+        let (width, big_endian) = match self {
+            LengthPrefix::Varint => {
+                let mut len: u64 = 0;
+                for i in 0..Self::MAX_HEADER_LEN {
+                    let Some(byte) = next_byte() else {
+                        return Header::Incomplete { needed: None };
+                    };
+                    let bits = u64::from(byte & 0x7F);
+                    if i == Self::MAX_HEADER_LEN - 1 && bits > 1 {
+                        return Header::Invalid;
+                    }
+                    len |= bits << (7 * i);
+                    if byte & 0x80 == 0 {
+                        return match usize::try_from(len) {
+                            Ok(len) => Header::Complete {
+                                len,
+                                header_len: i + 1,
+                            },
+                            Err(_) => Header::Invalid,
+                        };
+                    }
+                }
+                return Header::Invalid;
+            }
+            LengthPrefix::U16Be => (2, true),
+            LengthPrefix::U16Le => (2, false),
+            LengthPrefix::U32Be => (4, true),
+            LengthPrefix::U32Le => (4, false),
+        };
+
+        let mut len: u64 = 0;
+        for i in 0..width {
+            let Some(byte) = next_byte() else {
+                return Header::Incomplete {
+                    needed: Some(width - i),
+                };
+            };
+            if big_endian {
+                len = (len << 8) | u64::from(byte);
+            } else {
+                len |= u64::from(byte) << (8 * i);
+            }
+        }
+        match usize::try_from(len) {
+            Ok(len) => Header::Complete {
+                len,
+                header_len: width,
+            },
+            Err(_) => Header::Invalid,
+        }
+    }
+}
+
+/// Decode the COBS `frame` (without its trailing zero) in place, returning the length of the
+/// decoded bytes at the start of `frame`.
+///
+/// Returns `None` if a code byte points past the end of the frame.
+pub(crate) fn cobs_decode_in_place(frame: &mut [u8]) -> Option<usize> {
+    let (mut read, mut written) = (0, 0);
+    while read < frame.len() {
+        let code = usize::from(frame[read]);
+        if code == 0 || read + code > frame.len() {
+            return None;
+        }
+        frame.copy_within(read + 1..read + code, written);
+        written += code - 1;
+        read += code;
+        // A full block isn't followed by a zero, and neither is the last one
+        if code != 0xFF && read < frame.len() {
+            frame[written] = 0;
+            written += 1;
+        }
+    }
+    Some(written)
+}
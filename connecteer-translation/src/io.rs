@@ -1,97 +1,287 @@
-/// This type is meant to represent a type that Needs to be dropped, otherwise the process will
-/// abort by triggering two panics
-///
-/// You may not leak this object as it will be caught
-pub struct SignalDrop<T: ?Sized>(std::ptr::NonNull<T>, *mut bool);
-
-impl<T: ?Sized> SignalDrop<T> {
-    // this function will panic if the value given as an argument isn't dropped when the closure
-    // returns
-    //
-    // This is designed to be an aborting panic (double panic, so there will be no way to recover);
-    pub(crate) fn run_with_val<R>(val: &mut T, code: impl FnOnce(Self) -> R) -> R {
-        let mut signal = false;
-
-        let ret = code(Self(std::ptr::NonNull::from(val), &mut signal));
-
-        if !signal {
-            struct PanicOnDrop;
-            impl Drop for PanicOnDrop {
-                fn drop(&mut self) {
-                    panic!("This is a panic to abort the process")
+/// A reader that will only yield the first `remaining` bytes of the inner reader
+pub(crate) struct LimitedRead<R> {
+    inner: R,
+    remaining: usize,
+}
+
+impl<R> LimitedRead<R> {
+    pub(crate) fn new(inner: R, limit: usize) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+        }
+    }
+}
+
+impl<R: embedded_io::Io> embedded_io::Io for LimitedRead<R> {
+    type Error = R::Error;
+}
+
+impl<R: embedded_io::blocking::Read> embedded_io::blocking::Read for LimitedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let max = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..max])?;
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+/// A writer that throws away everything written to it, only keeping track of how many bytes
+/// went through
+pub(crate) struct CountingWrite {
+    count: usize,
+}
+
+impl CountingWrite {
+    pub(crate) fn new() -> Self {
+        Self { count: 0 }
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl embedded_io::Io for CountingWrite {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_io::blocking::Write for CountingWrite {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.count += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A writer encoding everything written to it with COBS, the frame is only ended (with a zero
+/// byte) by [`CobsWrite::finish`]
+pub(crate) struct CobsWrite<W> {
+    inner: W,
+    /// The non-zero bytes written since the last zero, waiting for their code byte
+    block: [u8; 254],
+    len: usize,
+}
+
+impl<W: embedded_io::blocking::Write> CobsWrite<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            block: [0; 254],
+            len: 0,
+        }
+    }
+
+    /// Write the pending block, preceded by its code byte
+    fn write_block(&mut self) -> Result<(), W::Error> {
+        self.inner.write_all(&[self.len as u8 + 1])?;
+        self.inner.write_all(&self.block[..self.len])?;
+        self.len = 0;
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), W::Error> {
+        self.write_block()?;
+        self.inner.write_all(&[0])
+    }
+}
+
+impl<W: embedded_io::Io> embedded_io::Io for CobsWrite<W> {
+    type Error = W::Error;
+}
+
+impl<W: embedded_io::blocking::Write> embedded_io::blocking::Write for CobsWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for &byte in buf {
+            if byte == 0 {
+                self.write_block()?;
+            } else {
+                self.block[self.len] = byte;
+                self.len += 1;
+                // A full block isn't followed by a zero
+                if self.len == self.block.len() {
+                    self.write_block()?;
                 }
             }
+        }
+        Ok(buf.len())
+    }
 
-            let _p = PanicOnDrop;
-            panic!("A SignalDrop as been leaked that shouldn't have. Please check anywhere you were given an `SignalDrop` as a parameter in a closure, and be sure that nothing leaked it");
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
+}
+
+/// A writer computing the checksum of everything written through it
+pub(crate) struct ChecksumWrite<W> {
+    inner: W,
+    crc: crate::checksum::Crc,
+}
+
+impl<W> ChecksumWrite<W> {
+    pub(crate) fn new(inner: W, checksum: crate::Checksum) -> Self {
+        Self {
+            inner,
+            crc: crate::checksum::Crc::new(checksum),
         }
+    }
 
-        ret
+    pub(crate) fn into_parts(self) -> (W, crate::checksum::Crc) {
+        (self.inner, self.crc)
     }
 }
 
-impl<T: ?Sized> Drop for SignalDrop<T> {
-    fn drop(&mut self) {
-        // This is safe since the pointer points to a variable that is still valid due to the
-        // contract "signed" when creating this Wrapper.
-        //
-        // Check SignalDropped::new() to see more info on said contract
-        unsafe { self.1.write(true) }
+impl<W: embedded_io::Io> embedded_io::Io for ChecksumWrite<W> {
+    type Error = W::Error;
+}
+
+impl<W: embedded_io::blocking::Write> embedded_io::blocking::Write for ChecksumWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
     }
 }
 
-impl embedded_io::Io
-    for SignalDrop<dyn embedded_io::blocking::Read<Error = core::convert::Infallible>>
-{
-    type Error = core::convert::Infallible;
+/// Stands in for the error of a [`RecordingWrite`] inner writer, which is kept aside
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WriteFailed;
+
+impl embedded_io::Error for WriteFailed {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        embedded_io::ErrorKind::Other
+    }
 }
 
-impl embedded_io::blocking::Read
-    for SignalDrop<dyn embedded_io::blocking::Read<Error = core::convert::Infallible>>
-{
-    #[inline(always)]
-    fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, Self::Error> {
-        unsafe { self.0.as_mut().read(buf) }
+/// A writer that keeps the first error of the inner writer, so it can still be returned after a
+/// serializer turned it into one of its own errors
+pub(crate) struct RecordingWrite<W: embedded_io::Io> {
+    inner: W,
+    error: Option<W::Error>,
+}
+
+impl<W: embedded_io::Io> RecordingWrite<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, error: None }
     }
 
-    #[inline(always)]
-    fn read_exact(
-        &mut self,
-        buf: &mut [u8],
-    ) -> core::result::Result<(), embedded_io::blocking::ReadExactError<Self::Error>> {
-        unsafe { self.0.as_mut().read_exact(buf) }
+    pub(crate) fn take_error(&mut self) -> Option<W::Error> {
+        self.error.take()
     }
 }
 
-impl<WriteError: embedded_io::Error> embedded_io::Io
-    for SignalDrop<dyn embedded_io::blocking::Write<Error = WriteError>>
-{
-    type Error = WriteError;
+impl<W: embedded_io::Io> embedded_io::Io for RecordingWrite<W> {
+    type Error = WriteFailed;
 }
 
-impl<WriteError: embedded_io::Error> embedded_io::blocking::Write
-    for SignalDrop<dyn embedded_io::blocking::Write<Error = WriteError>>
-{
-    #[inline(always)]
-    fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, WriteError> {
-        unsafe { self.0.as_mut().write(buf) }
+impl<W: embedded_io::blocking::Write> embedded_io::blocking::Write for RecordingWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.inner.write(buf).map_err(|e| {
+            self.error.get_or_insert(e);
+            WriteFailed
+        })
     }
 
-    #[inline(always)]
-    fn flush(&mut self) -> core::result::Result<(), WriteError> {
-        unsafe { self.0.as_mut().flush() }
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().map_err(|e| {
+            self.error.get_or_insert(e);
+            WriteFailed
+        })
+    }
+}
+
+/// A writer filling a slice, failing with [`Overflow`](crate::Overflow) once it is full
+pub(crate) struct SliceWrite<'a> {
+    /// The part of the slice that hasn't been written yet
+    free: &'a mut [u8],
+    written: usize,
+}
+
+impl<'a> SliceWrite<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            free: buf,
+            written: 0,
+        }
     }
 
-    #[inline(always)]
-    fn write_all(&mut self, buf: &[u8]) -> core::result::Result<(), WriteError> {
-        unsafe { self.0.as_mut().write_all(buf) }
+    pub(crate) fn written(&self) -> usize {
+        self.written
     }
+}
 
-    #[inline(always)]
-    fn write_fmt(
-        &mut self,
-        fmt: core::fmt::Arguments<'_>,
-    ) -> core::result::Result<(), embedded_io::blocking::WriteFmtError<WriteError>> {
-        unsafe { self.0.as_mut().write_fmt(fmt) }
+impl embedded_io::Io for SliceWrite<'_> {
+    type Error = crate::Overflow;
+}
+
+impl embedded_io::blocking::Write for SliceWrite<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if self.free.is_empty() && !buf.is_empty() {
+            return Err(crate::Overflow);
+        }
+        let n = self.free.len().min(buf.len());
+        let (head, tail) = core::mem::take(&mut self.free).split_at_mut(n);
+        head.copy_from_slice(&buf[..n]);
+        self.free = tail;
+        self.written += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// What happened while a deserializer was reading from the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct ReadStats {
+    /// The number of bytes handed out to the deserializer
+    pub(crate) read: usize,
+    /// Whether the deserializer asked for more bytes than there were available
+    pub(crate) reached_end: bool,
+    /// How many bytes the read that reached the end was short of
+    pub(crate) short_by: usize,
+}
+
+/// A reader that keeps track of the number of bytes read, and if the end of the inner reader was
+/// reached
+pub(crate) struct TrackedRead<R> {
+    inner: R,
+    stats: ReadStats,
+}
+
+impl<R> TrackedRead<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            stats: ReadStats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> ReadStats {
+        self.stats
+    }
+}
+
+impl<R: embedded_io::Io> embedded_io::Io for TrackedRead<R> {
+    type Error = R::Error;
+}
+
+impl<R: embedded_io::blocking::Read> embedded_io::blocking::Read for TrackedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let n = self.inner.read(buf)?;
+        self.stats.read += n;
+        if n == 0 && !buf.is_empty() {
+            self.stats.reached_end = true;
+            self.stats.short_by = buf.len();
+        }
+        Ok(n)
     }
 }
@@ -1,166 +1,1109 @@
+#![no_std]
+#![forbid(unsafe_code)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
 pub use embedded_io;
 
+#[cfg(feature = "tokio")]
+pub mod async_connection;
 pub mod buffer;
-pub mod io;
+pub mod builder;
+pub mod checksum;
+pub mod compression;
+#[cfg(feature = "chacha20poly1305")]
+pub mod encryption;
+mod envelope;
+pub mod error;
+pub mod framing;
+mod io;
+pub mod limits;
+pub mod protocol;
+#[cfg(feature = "std")]
+pub mod stream;
+
+#[cfg(feature = "tokio")]
+pub use async_connection::AsyncConnection;
+pub use builder::ConnectionBuilder;
+pub use checksum::Checksum;
+pub use compression::Compression;
+#[cfg(feature = "chacha20poly1305")]
+pub use encryption::{Encryption, Role};
+pub use error::Error;
+pub use framing::{Framing, FramingError, LengthPrefix};
+pub use limits::{Limits, OversizedPolicy};
+pub use protocol::{BorrowingProtocol, Protocol};
+#[cfg(feature = "std")]
+pub use stream::StreamConnection;
 
 pub const DEFAULT_BUFFER_SIZE: usize = 4096;
 
+/// The reason why [`Connection::try_deserialize`] didn't yield a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError<DeserializerError> {
+    /// The buffer doesn't hold a whole message yet, more bytes need to be fed before trying again.
+    ///
+    /// `needed` is the minimum number of bytes that are missing, when it is known.
+    Incomplete { needed: Option<usize> },
+    /// The buffered bytes aren't a valid message
+    Invalid(DeserializerError),
+    /// The buffered bytes can't be split into frames, they are dropped like an invalid message
+    Framing(FramingError),
+    /// The message doesn't fit in the [`Limits`] of the connection, the [`OversizedPolicy`] tells
+    /// what happened to its bytes
+    Oversized,
+    /// The [`Checksum`] trailer doesn't match the message, which was corrupted on the way.
+    ///
+    /// The message is left in the buffer like an invalid one.
+    ChecksumMismatch,
+    /// The encrypted message was forged, corrupted, or replayed.
+    ///
+    /// The message is left in the buffer like an invalid one.
+    Unauthenticated,
+    /// The connection is misconfigured: [`Compression`] or encryption is enabled, but its bytes
+    /// can only be delimited by a [`Framing::LengthPrefix`] or a [`Framing::Cobs`].
+    ///
+    /// Nothing was read from the buffer.
+    Unframed,
+}
+
+/// The reason why [`Connection::serialize`] (or one of its variants) didn't write a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerializeError<SerializerError, WriteError> {
+    /// The message can't be serialized by the protocol
+    Serialize(SerializerError),
+    /// The serialized message couldn't be written out, like when a fixed size buffer is full.
+    ///
+    /// The writer may already hold part of the message.
+    Write(WriteError),
+    /// The connection is misconfigured, see [`DeserializeError::Unframed`].
+    ///
+    /// Nothing was written.
+    Unframed,
+}
+
+/// The output slice is too small to hold the serialized message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow;
+
+impl embedded_io::Error for Overflow {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        embedded_io::ErrorKind::Other
+    }
+}
+
+impl core::fmt::Display for Overflow {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("the output slice is too small for the message")
+    }
+}
+
+impl core::error::Error for Overflow {}
+
+/// A serde [`Protocol`] over a byte stream: `Outgoing` messages are serialized into `OutBuffer`s,
+/// and `Incoming` ones are read back from the bytes fed into `InBuffer`.
+///
+/// Both ends of a connection can be described by the same type with
+/// [`Connection::reverse`], when they don't send the same messages (like a client sending
+/// requests and receiving responses).
 pub struct Connection<
-    ProtocolDe,
-    ProtocolSer,
-    ProtocolDeFactory,
-    ProtocolSerFactory,
-    InBuffer,
-    OutBufferFactory,
-    OutBuffer,
-    WriteError,
-    Message,
+    Protocol,
+    Incoming,
+    #[cfg(feature = "alloc")] Outgoing = Incoming,
+    #[cfg(not(feature = "alloc"))] Outgoing,
+    #[cfg(feature = "alloc")] InBuffer = buffer::RingBuffer,
+    #[cfg(not(feature = "alloc"))] InBuffer,
+    #[cfg(feature = "alloc")] OutBufferFactory = fn() -> alloc::vec::Vec<u8>,
+    #[cfg(not(feature = "alloc"))] OutBufferFactory,
+    #[cfg(feature = "alloc")] OutBuffer = alloc::vec::Vec<u8>,
+    #[cfg(not(feature = "alloc"))] OutBuffer,
 > {
     buffer: InBuffer,
+    framing: Framing,
+    envelope: envelope::Envelope,
+    limits: Limits,
+    /// Bytes at the start of the buffer that are discarded the next time it is used: the last
+    /// message returned by [`Connection::try_deserialize_borrowed`], or a dropped oversized one
+    pending_discard: usize,
+    /// Bytes of a dropped oversized message that weren't fed yet, they are skipped when they are
+    pending_skip: usize,
+    /// The buffer length the message at the start of the buffer is known to need, it isn't
+    /// deserialized again before that many bytes are buffered
+    awaited: usize,
     buffer_factory: OutBufferFactory,
-    protocol_de_factory: ProtocolDeFactory,
-    protocol_ser_factory: ProtocolSerFactory,
-    message_marker: std::marker::PhantomData<fn() -> Message>,
-    buffer_marker: std::marker::PhantomData<fn() -> (OutBuffer, WriteError)>,
-    protocol_marker: std::marker::PhantomData<fn() -> (ProtocolSer, ProtocolDe)>,
-}
-
-impl<
-        ProtocolDe,
-        ProtocolSer,
-        ProtocolDeFactory,
-        ProtocolSerFactory,
-        InBuffer,
-        OutBufferFactory,
-        OutBuffer,
-        WriteError,
-        Message: serde::Serialize + serde::de::DeserializeOwned,
-        DeserializerError,
-        SerializerError,
-    >
-    Connection<
-        ProtocolDe,
-        ProtocolSer,
-        ProtocolDeFactory,
-        ProtocolSerFactory,
-        InBuffer,
-        OutBufferFactory,
-        OutBuffer,
-        WriteError,
-        Message,
-    >
+    protocol: Protocol,
+    message_marker: core::marker::PhantomData<fn() -> (Incoming, Outgoing)>,
+    buffer_marker: core::marker::PhantomData<fn() -> OutBuffer>,
+}
+
+/// A [`Connection`] speaking JSON, with the default buffers
+#[cfg(feature = "json")]
+pub type JsonConnection<Incoming, Outgoing = Incoming> =
+    Connection<protocol::Json, Incoming, Outgoing>;
+
+/// A [`Connection`] speaking MessagePack, with the default buffers
+#[cfg(feature = "messagepack")]
+pub type MessagePackConnection<Incoming, Outgoing = Incoming> =
+    Connection<protocol::MessagePack, Incoming, Outgoing>;
+
+/// A [`Connection`] speaking Postcard, with the default buffers
+#[cfg(all(feature = "postcard", feature = "alloc"))]
+pub type PostcardConnection<Incoming, Outgoing = Incoming> =
+    Connection<protocol::Postcard, Incoming, Outgoing>;
+
+/// A [`Connection`] speaking CBOR, with the default buffers
+#[cfg(feature = "cbor")]
+pub type CborConnection<Incoming, Outgoing = Incoming> =
+    Connection<protocol::Cbor, Incoming, Outgoing>;
+
+/// A [`Connection`] speaking RON, with the default buffers
+#[cfg(feature = "ron")]
+pub type RonConnection<Incoming, Outgoing = Incoming> =
+    Connection<protocol::Ron, Incoming, Outgoing>;
+
+/// A [`Connection`] that holds the received bytes in a
+/// [`StaticRingBuffer`](buffer::StaticRingBuffer) of `N` bytes
+pub type StaticConnection<
+    Protocol,
+    Incoming,
+    Outgoing,
+    const N: usize,
+    OutBufferFactory,
+    OutBuffer,
+> = Connection<
+    Protocol,
+    Incoming,
+    Outgoing,
+    buffer::StaticRingBuffer<N>,
+    OutBufferFactory,
+    OutBuffer,
+>;
+
+impl<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
 where
-    ProtocolDeFactory: FnMut(
-        io::SignalDrop<dyn embedded_io::blocking::Read<Error = core::convert::Infallible>>,
-    ) -> ProtocolDe,
-    ProtocolSerFactory:
-        FnMut(io::SignalDrop<dyn embedded_io::blocking::Write<Error = WriteError>>) -> ProtocolSer,
-    InBuffer: buffer::Buffer + 'static,
+    InBuffer: buffer::Buffer,
     OutBufferFactory: FnMut() -> OutBuffer,
-    OutBuffer: embedded_io::blocking::Write<Error = WriteError> + 'static,
-    for<'r> &'r mut ProtocolSer: serde::Serializer<Error = SerializerError>,
-    for<'r, 'de> &'r mut ProtocolDe: serde::Deserializer<'de, Error = DeserializerError>,
 {
     pub fn new(
-        ser_factory: ProtocolSerFactory,
-        de_factory: ProtocolDeFactory,
+        protocol: Protocol,
         buffer_factory: OutBufferFactory,
         inner_buffer: InBuffer,
     ) -> Self {
         Self {
             buffer: inner_buffer,
-            protocol_de_factory: de_factory,
-            protocol_ser_factory: ser_factory,
+            framing: Framing::None,
+            envelope: envelope::Envelope::new(),
+            limits: Limits::default(),
+            pending_discard: 0,
+            pending_skip: 0,
+            awaited: 0,
+            protocol,
             buffer_factory,
             buffer_marker: core::marker::PhantomData,
             message_marker: core::marker::PhantomData,
-            protocol_marker: core::marker::PhantomData,
         }
     }
 
+    /// Set how messages are delimited on the byte stream
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Follow every message with a checksum, checked when it is deserialized
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.envelope.checksum = Some(checksum);
+        self
+    }
+
+    pub fn checksum(&self) -> Option<Checksum> {
+        self.envelope.checksum
+    }
+
+    /// Compress the serialized messages, which requires a length prefix or COBS framing: with any
+    /// other framing, messages are refused as [`DeserializeError::Unframed`]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.envelope.compression = Some(compression);
+        self
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.envelope.compression
+    }
+
+    /// Encrypt and authenticate every message, which requires a length prefix or COBS framing:
+    /// with any other framing, messages are refused as [`DeserializeError::Unframed`]
+    #[cfg(feature = "chacha20poly1305")]
+    pub fn with_encryption(mut self, encryption: Encryption) -> Self {
+        self.envelope.encryption = Some(encryption);
+        self
+    }
+
+    /// Bound the number of bytes buffered, and the size of a single message
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// The input buffer, to check how full it is
+    pub fn buffer(&self) -> &InBuffer {
+        &self.buffer
+    }
+
+    /// The number of buffered bytes that aren't part of a returned message yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() - self.pending_discard
+    }
+
+    /// Swap the incoming and outgoing message types, giving the connection used by the other end.
+    ///
+    /// The protocol, framing, checksum, compression and buffers are kept, along with any buffered
+    /// byte. The encryption is kept as it is, role and nonce counters included: the other end
+    /// needs its own `Encryption`, with the other `Role`, or both ends would reuse each other's
+    /// nonces.
+    pub fn reverse(
+        self,
+    ) -> Connection<Protocol, Outgoing, Incoming, InBuffer, OutBufferFactory, OutBuffer> {
+        Connection {
+            buffer: self.buffer,
+            framing: self.framing,
+            envelope: self.envelope,
+            limits: self.limits,
+            pending_discard: self.pending_discard,
+            pending_skip: self.pending_skip,
+            awaited: self.awaited,
+            buffer_factory: self.buffer_factory,
+            protocol: self.protocol,
+            message_marker: core::marker::PhantomData,
+            buffer_marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Copy `bytes` into the input buffer, returning how many of them were taken.
+    ///
+    /// Bytes that don't fit (in a fixed size buffer, or past [`Limits::max_buffered`]) are left
+    /// out, and should be fed again once messages are read out of the buffer.
     pub fn feed_bytes(&mut self, bytes: &[u8]) -> usize {
-        self.buffer.feed_bytes(bytes)
-    }
-
-    pub fn try_deserialize(&mut self) -> Result<Message, DeserializerError>
-where {
-        // this is because BufferRead as an non 'static lifetime otherwise and it doesn't work
-        // Here there are runtime checks in place so that there isn't any memory corruption
-        // possible as the process will be aborted if the value is leaked.
-        let mut buf: InBuffer::Reader<'static> =
-            unsafe { std::mem::transmute(self.buffer.get_read()) };
-        io::SignalDrop::<dyn embedded_io::blocking::Read<Error = core::convert::Infallible>>::run_with_val(
-            &mut buf,
-            |s| {
-                let mut deserializer = (self.protocol_de_factory)(s);
-
-                Message::deserialize(&mut deserializer)
-            },
-        )
+        self.discard_pending();
+        let skipped = self.pending_skip.min(bytes.len());
+        self.pending_skip -= skipped;
+        let bytes = &bytes[skipped..];
+        let room = self.limits.room(self.buffer.len());
+        skipped + self.buffer.feed_bytes(&bytes[..bytes.len().min(room)])
     }
 
-    pub fn serialize(&mut self, value: Message) -> Result<OutBuffer, SerializerError>
-where {
-        let mut buf = (self.buffer_factory)();
-        let res =
-            io::SignalDrop::<dyn embedded_io::blocking::Write<Error = WriteError>>::run_with_val::<
-                Result<_, SerializerError>,
-            >(&mut buf, |s| {
-                let mut serializer = (self.protocol_ser_factory)(s);
+    /// Drop the message at the start of the buffer, so the next one can be read after it was
+    /// reported as [`DeserializeError::Invalid`] or [`DeserializeError::Framing`].
+    ///
+    /// With a length prefix, the whole frame is dropped, and the part of it that wasn't fed yet is
+    /// skipped as it is. A header that can't be read is dropped one byte at a time. Without
+    /// framing, there is no telling where the next message starts, so every buffered byte is
+    /// dropped. With COBS, invalid frames are dropped as soon as they are reported, so there is
+    /// nothing left to drop. With lines, everything up to the next newline is dropped, once it is
+    /// received. An oversized message dropped by [`OversizedPolicy::Resync`] is already gone too,
+    /// the next one is left alone.
+    ///
+    /// Returns the number of bytes dropped.
+    pub fn skip_to_next_frame(&mut self) -> usize {
+        if self.pending_discard > 0 || self.pending_skip > 0 {
+            self.discard_pending();
+            return 0;
+        }
+        let len = match self.framing {
+            Framing::None => self.buffer.len(),
+            Framing::LengthPrefix(prefix) => {
+                let mut buf = self.buffer.get_read();
+                let header = prefix.read_header(&mut buf);
+                drop(buf);
+                self.buffer.keep_read_bytes();
+                match header {
+                    framing::Header::Complete { len, header_len } => header_len + len,
+                    framing::Header::Incomplete { .. } => 0,
+                    framing::Header::Invalid => 1,
+                }
+            }
+            Framing::Cobs => 0,
+            // Empty lines are skipped before the reported one, and dropped along with it
+            Framing::Lines => {
+                delimited_frame(self.buffer.contiguous(), b'\n').map_or(0, |(_, end)| end + 1)
+            }
+        };
+        self.pending_discard = len.min(self.buffer.len());
+        self.pending_skip = len - self.pending_discard;
+        self.discard_pending();
+        len
+    }
 
-                value.serialize(&mut serializer).map(|_| ())
+    /// Discard the bytes that were left in the buffer after the last message, nothing can borrow
+    /// them anymore
+    fn discard_pending(&mut self) {
+        if self.pending_discard > 0 {
+            let mut buf = self.buffer.get_read();
+            skip_bytes(&mut buf, self.pending_discard);
+            drop(buf);
+            self.buffer.discard_read_bytes();
+            self.pending_discard = 0;
+            self.awaited = 0;
+        }
+    }
+
+    /// Report the message at the start of the buffer as incomplete, remembering how many bytes it
+    /// needs so it isn't deserialized again before they are fed
+    fn incomplete<E>(&mut self, needed: Option<usize>) -> DeserializeError<E> {
+        if let Some(needed) = needed {
+            self.awaited = self.buffer.len() + needed;
+        }
+        DeserializeError::Incomplete { needed }
+    }
+
+    /// The bytes still missing from the message at the start of the buffer, when more are known to
+    /// be needed before trying to deserialize it again
+    fn still_awaited(&self) -> Option<usize> {
+        let buffered = self.buffer.len();
+        // A stuck message goes through the usual checks, to be reported as oversized
+        (buffered < self.awaited && !self.limits.stuck(buffered)).then(|| self.awaited - buffered)
+    }
+
+    /// Report a message that doesn't fit in the limits, dropping it if the policy says so.
+    ///
+    /// `len` is the length of the message, when it is known.
+    fn oversized<E>(&mut self, len: Option<usize>) -> DeserializeError<E> {
+        (self.pending_discard, self.pending_skip) = self.limits.dropped(len, self.buffer.len());
+        DeserializeError::Oversized
+    }
+
+    /// Check the checksum trailer after the `len` bytes of the message at the start of the
+    /// buffer, then drop both of them. Returns the number of bytes dropped.
+    fn check_trailer<E>(
+        &mut self,
+        checksum: Checksum,
+        len: usize,
+    ) -> Result<usize, DeserializeError<E>> {
+        let len = len + checksum.trailer_len();
+        let buffered = self.buffer.len();
+        if buffered < len {
+            if self.limits.stuck(buffered) {
+                return Err(self.oversized(Some(len)));
+            }
+            return Err(self.incomplete(Some(len - buffered)));
+        }
+        envelope::verify_trailer(Some(checksum), &self.buffer.contiguous()[..len], false)?;
+        self.pending_discard = len;
+        self.discard_pending();
+        Ok(len)
+    }
+}
+
+impl<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+where
+    Protocol: protocol::BorrowingProtocol,
+    InBuffer: buffer::Buffer,
+    OutBufferFactory: FnMut() -> OutBuffer,
+{
+    /// Deserialize a message borrowing from the buffered bytes, like a `&str` or `&[u8]` field,
+    /// without copying it out.
+    ///
+    /// The buffered bytes are first moved so they are contiguous. The message bytes stay in the
+    /// buffer as long as the returned guard (or anything borrowed from it) lives, and are
+    /// discarded the next time the connection is used.
+    pub fn try_deserialize_borrowed<'c, T>(
+        &'c mut self,
+    ) -> Result<BorrowedMessage<'c, T>, DeserializeError<Protocol::DeserializerError>>
+    where
+        T: serde::Deserialize<'c>,
+    {
+        self.discard_pending();
+        if let Some(needed) = self.still_awaited() {
+            return Err(DeserializeError::Incomplete {
+                needed: Some(needed),
             });
+        }
+        let Self {
+            buffer,
+            framing,
+            envelope,
+            limits,
+            pending_discard,
+            pending_skip,
+            awaited,
+            protocol,
+            ..
+        } = self;
+        let bytes = buffer.contiguous();
+        let buffered = bytes.len();
+        // The buffer stays borrowed whatever happens, dropped bytes are discarded later on
+        let mut oversized = |len: Option<usize>| {
+            (*pending_discard, *pending_skip) = limits.dropped(len, buffered);
+            DeserializeError::Oversized
+        };
+        let mut incomplete = |needed: usize| {
+            *awaited = buffered + needed;
+            DeserializeError::Incomplete {
+                needed: Some(needed),
+            }
+        };
+
+        let (message, len) = match *framing {
+            Framing::None | Framing::Lines if envelope.is_binary() => {
+                return Err(DeserializeError::Unframed)
+            }
+            Framing::None => {
+                let bytes: &[u8] = bytes;
+                let trailer_len = envelope.checksum.map_or(0, Checksum::trailer_len);
+                match protocol.deserialize_borrowed(bytes) {
+                    Ok((_, len)) if limits.too_long(len + trailer_len) => {
+                        return Err(oversized(Some(len + trailer_len)))
+                    }
+                    Ok((message, len)) => {
+                        let len = len + trailer_len;
+                        let Some(checked) = bytes.get(..len) else {
+                            if limits.stuck(buffered) {
+                                return Err(oversized(Some(len)));
+                            }
+                            return Err(incomplete(len - buffered));
+                        };
+                        envelope::verify_trailer(envelope.checksum, checked, false)?;
+                        (message, len)
+                    }
+                    Err(DeserializeError::Incomplete { .. }) if limits.stuck(buffered) => {
+                        return Err(oversized(None))
+                    }
+                    Err(DeserializeError::Incomplete {
+                        needed: Some(needed),
+                    }) => return Err(incomplete(needed)),
+                    Err(e) => return Err(e),
+                }
+            }
+            Framing::LengthPrefix(prefix) => {
+                let mut frame: &[u8] = &*bytes;
+                let (len, header_len) = match prefix.read_header(&mut frame) {
+                    framing::Header::Complete { len, header_len } => (len, header_len),
+                    framing::Header::Incomplete { .. } if limits.stuck(buffered) => {
+                        return Err(oversized(None))
+                    }
+                    framing::Header::Incomplete { needed } => {
+                        return Err(DeserializeError::Incomplete { needed })
+                    }
+                    framing::Header::Invalid => {
+                        return Err(DeserializeError::Framing(FramingError::InvalidLengthPrefix))
+                    }
+                };
+                if limits.too_long(header_len + len) {
+                    return Err(oversized(Some(header_len + len)));
+                }
+                if frame.len() < len {
+                    if limits.stuck(buffered) {
+                        return Err(oversized(Some(header_len + len)));
+                    }
+                    return Err(incomplete(len - frame.len()));
+                }
+                let frame = envelope.open(&mut bytes[header_len..header_len + len], false)?;
+                let frame = Compression::uncompressed(envelope.compression, frame)?;
+                (deserialize_frame(protocol, frame)?.0, header_len + len)
+            }
+            Framing::Cobs => {
+                let (start, end) = match delimited_frame(bytes, 0) {
+                    Some((start, end)) if limits.too_long(end + 1 - start) => {
+                        return Err(oversized(Some(end + 1)))
+                    }
+                    Some(frame) => frame,
+                    None if limits.stuck(buffered) => return Err(oversized(None)),
+                    None => return Err(DeserializeError::Incomplete { needed: None }),
+                };
+                // The frame is dropped even when it is invalid, since it is decoded in place
+                *pending_discard = end + 1;
+                let Some(decoded) = framing::cobs_decode_in_place(&mut bytes[start..end]) else {
+                    return Err(DeserializeError::Framing(FramingError::InvalidCobs));
+                };
+                let frame = envelope.open(&mut bytes[start..start + decoded], false)?;
+                let frame = Compression::uncompressed(envelope.compression, frame)?;
+                (deserialize_frame(protocol, frame)?.0, end + 1)
+            }
+            Framing::Lines => {
+                let (start, end) = match delimited_frame(bytes, b'\n') {
+                    Some((start, end)) if limits.too_long(end + 1 - start) => {
+                        return Err(oversized(Some(end + 1)))
+                    }
+                    Some(frame) => frame,
+                    None if limits.stuck(buffered) => return Err(oversized(None)),
+                    None => return Err(DeserializeError::Incomplete { needed: None }),
+                };
+                let frame = envelope.open(&mut bytes[start..end], true)?;
+                let (message, len) = deserialize_frame(protocol, frame)?;
+                // A line holds a single message
+                if !frame[len..].iter().all(u8::is_ascii_whitespace) {
+                    return Err(DeserializeError::Invalid(
+                        <Protocol::DeserializerError as serde::de::Error>::custom(
+                            "trailing bytes after the message",
+                        ),
+                    ));
+                }
+                (message, end + 1)
+            }
+        };
+
+        *pending_discard = len;
+        Ok(BorrowedMessage {
+            message,
+            len,
+            buffer_marker: core::marker::PhantomData,
+        })
+    }
+}
+
+/// Deserialize a message that has to fit in `frame`, returning it along with the number of bytes
+/// it took
+fn deserialize_frame<'de, Protocol, T>(
+    protocol: &mut Protocol,
+    frame: &'de [u8],
+) -> Result<(T, usize), DeserializeError<Protocol::DeserializerError>>
+where
+    Protocol: protocol::BorrowingProtocol,
+    T: serde::Deserialize<'de>,
+{
+    match protocol.deserialize_borrowed(frame) {
+        Ok(res) => Ok(res),
+        // The whole frame is there, so the message itself is cut short
+        Err(DeserializeError::Incomplete { .. }) => Err(DeserializeError::Invalid(
+            <Protocol::DeserializerError as serde::de::Error>::custom(
+                "message longer than its frame",
+            ),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// Find the first frame in `bytes` ended by `delimiter`, as the position of its first byte and of
+/// its delimiter. Empty frames carry no message, they are skipped.
+fn delimited_frame(bytes: &[u8], delimiter: u8) -> Option<(usize, usize)> {
+    let start = bytes.iter().take_while(|&&byte| byte == delimiter).count();
+    let end = start + bytes[start..].iter().position(|&byte| byte == delimiter)?;
+    Some((start, end))
+}
+
+/// A message borrowing from the buffer of a [`Connection`], returned by
+/// [`Connection::try_deserialize_borrowed`]
+#[derive(Debug)]
+pub struct BorrowedMessage<'c, T> {
+    message: T,
+    len: usize,
+    buffer_marker: core::marker::PhantomData<&'c [u8]>,
+}
+
+impl<'c, T> BorrowedMessage<'c, T> {
+    /// The number of bytes the message took in the buffer
+    pub fn byte_len(&self) -> usize {
+        self.len
+    }
+
+    pub fn into_inner(self) -> T {
+        self.message
+    }
+}
+
+impl<T> core::ops::Deref for BorrowedMessage<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.message
+    }
+}
+
+impl<T> core::ops::DerefMut for BorrowedMessage<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.message
+    }
+}
+
+impl<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+where
+    Protocol: protocol::Protocol,
+    Incoming: serde::de::DeserializeOwned,
+    InBuffer: buffer::Buffer,
+    OutBufferFactory: FnMut() -> OutBuffer,
+{
+    pub fn try_deserialize(
+        &mut self,
+    ) -> Result<Incoming, DeserializeError<Protocol::DeserializerError>> {
+        self.try_deserialize_with_len().map(|(message, _)| message)
+    }
+
+    /// Same as [`Connection::try_deserialize`], but also returns the number of bytes the message
+    /// took in the buffer
+    pub fn try_deserialize_with_len(
+        &mut self,
+    ) -> Result<(Incoming, usize), DeserializeError<Protocol::DeserializerError>> {
+        self.discard_pending();
+        if let Some(needed) = self.still_awaited() {
+            return Err(DeserializeError::Incomplete {
+                needed: Some(needed),
+            });
+        }
+        match self.framing {
+            Framing::None | Framing::Lines if self.envelope.is_binary() => {
+                Err(DeserializeError::Unframed)
+            }
+            Framing::None => {
+                let trailer_len = self.envelope.checksum.map_or(0, Checksum::trailer_len);
+                let (res, stats) = self.deserialize_from(0, usize::MAX);
+                match res {
+                    Ok(_) if self.limits.too_long(stats.read + trailer_len) => {
+                        self.buffer.keep_read_bytes();
+                        Err(self.oversized(Some(stats.read + trailer_len)))
+                    }
+                    Ok(message) => match self.envelope.checksum {
+                        Some(checksum) => {
+                            self.buffer.keep_read_bytes();
+                            let len = self.check_trailer(checksum, stats.read)?;
+                            Ok((message, len))
+                        }
+                        None => {
+                            self.buffer.discard_read_bytes();
+                            self.awaited = 0;
+                            Ok((message, stats.read))
+                        }
+                    },
+                    Err(e) => {
+                        self.buffer.keep_read_bytes();
+                        // The deserializer wanted more than what was buffered, so the error is
+                        // most likely only an unexpected EOF
+                        if !stats.reached_end {
+                            Err(DeserializeError::Invalid(e))
+                        } else if self.limits.stuck(self.buffer.len()) {
+                            Err(self.oversized(None))
+                        } else {
+                            let needed = self
+                                .protocol
+                                .missing_bytes(self.buffer.contiguous(), stats.short_by);
+                            Err(self.incomplete(needed))
+                        }
+                    }
+                }
+            }
+            Framing::LengthPrefix(prefix) => self.try_deserialize_length_prefixed(prefix),
+            Framing::Cobs => self.try_deserialize_cobs(),
+            Framing::Lines => self.try_deserialize_line(),
+        }
+    }
+
+    fn try_deserialize_line(
+        &mut self,
+    ) -> Result<(Incoming, usize), DeserializeError<Protocol::DeserializerError>> {
+        let bytes = self.buffer.contiguous();
+        let (start, end) = match delimited_frame(bytes, b'\n') {
+            Some((start, end)) if self.limits.too_long(end + 1 - start) => {
+                return Err(self.oversized(Some(end + 1)))
+            }
+            Some(frame) => frame,
+            None if self.limits.stuck(self.buffer.len()) => return Err(self.oversized(None)),
+            None => return Err(DeserializeError::Incomplete { needed: None }),
+        };
+
+        let frame = self.envelope.open(&mut bytes[start..end], true)?;
+        let message = self
+            .protocol
+            .deserialize_line(frame)
+            .map_err(DeserializeError::Invalid)?;
+        self.pending_discard = end + 1;
+        self.discard_pending();
+        Ok((message, end + 1))
+    }
+
+    fn try_deserialize_cobs(
+        &mut self,
+    ) -> Result<(Incoming, usize), DeserializeError<Protocol::DeserializerError>> {
+        let bytes = self.buffer.contiguous();
+        let (start, end) = match delimited_frame(bytes, 0) {
+            Some((start, end)) if self.limits.too_long(end + 1 - start) => {
+                return Err(self.oversized(Some(end + 1)))
+            }
+            Some(frame) => frame,
+            None if self.limits.stuck(self.buffer.len()) => return Err(self.oversized(None)),
+            None => return Err(DeserializeError::Incomplete { needed: None }),
+        };
+
+        let res = match framing::cobs_decode_in_place(&mut bytes[start..end]) {
+            Some(decoded) => self
+                .envelope
+                .open(&mut bytes[start..start + decoded], false)
+                .and_then(|frame| {
+                    Compression::unpack(
+                        self.envelope.compression,
+                        &self.limits,
+                        frame,
+                        |serialized| {
+                            self.protocol
+                                .deserialize(serialized)
+                                .map_err(DeserializeError::Invalid)
+                        },
+                    )
+                }),
+            None => Err(DeserializeError::Framing(FramingError::InvalidCobs)),
+        };
+        // The frame is dropped even when it is invalid, since it was decoded in place
+        self.pending_discard = end + 1;
+        self.discard_pending();
+        res.map(|message| (message, end + 1))
+    }
+
+    fn try_deserialize_length_prefixed(
+        &mut self,
+        prefix: LengthPrefix,
+    ) -> Result<(Incoming, usize), DeserializeError<Protocol::DeserializerError>> {
+        // Look for a whole frame before handing anything to the deserializer
+        let (len, header_len) = {
+            let mut buf = self.buffer.get_read();
+            let (len, header_len) = match prefix.read_header(&mut buf) {
+                framing::Header::Complete { len, header_len } => (len, header_len),
+                framing::Header::Incomplete { needed } => {
+                    drop(buf);
+                    self.buffer.keep_read_bytes();
+                    if self.limits.stuck(self.buffer.len()) {
+                        return Err(self.oversized(None));
+                    }
+                    return Err(self.incomplete(needed));
+                }
+                framing::Header::Invalid => {
+                    drop(buf);
+                    self.buffer.keep_read_bytes();
+                    return Err(DeserializeError::Framing(FramingError::InvalidLengthPrefix));
+                }
+            };
+            drop(buf);
+            self.buffer.keep_read_bytes();
+            let available = (self.buffer.len() - header_len).min(len);
+            if self.limits.too_long(header_len + len)
+                || (available < len && self.limits.stuck(self.buffer.len()))
+            {
+                return Err(self.oversized(Some(header_len + len)));
+            }
+            if available < len {
+                return Err(self.incomplete(Some(len - available)));
+            }
+            (len, header_len)
+        };
+
+        let res = if self.envelope.is_empty() {
+            let (res, _) = self.deserialize_from(header_len, len);
+            res.map_err(DeserializeError::Invalid)
+        } else {
+            let frame = &mut self.buffer.contiguous()[header_len..header_len + len];
+            self.envelope.open(frame, false).and_then(|frame| {
+                Compression::unpack(
+                    self.envelope.compression,
+                    &self.limits,
+                    frame,
+                    |serialized| {
+                        self.protocol
+                            .deserialize(serialized)
+                            .map_err(DeserializeError::Invalid)
+                    },
+                )
+            })
+        };
 
         match res {
+            Ok(message) => {
+                // The frame is consumed whole, even if the deserializer didn't need every byte
+                self.buffer.keep_read_bytes();
+                let mut buf = self.buffer.get_read();
+                skip_bytes(&mut buf, header_len + len);
+                drop(buf);
+                self.buffer.discard_read_bytes();
+                self.awaited = 0;
+                Ok((message, header_len + len))
+            }
+            // Too large once decompressed, the frame goes the way of any oversized one
+            Err(DeserializeError::Oversized) => {
+                self.buffer.keep_read_bytes();
+                Err(self.oversized(Some(header_len + len)))
+            }
             Err(e) => {
                 self.buffer.keep_read_bytes();
                 Err(e)
             }
-            Ok(_) => {
-                self.buffer.discard_read_bytes();
-                Ok(buf)
+        }
+    }
+
+    /// Iterate over every message that can be decoded from the buffered bytes.
+    ///
+    /// The iterator stops at the first incomplete message, leaving its bytes in the buffer for
+    /// when the rest of it is fed. Any other [`DeserializeError`] (an invalid, oversized,
+    /// corrupted or forged message) is yielded as it is, and ends the iteration too.
+    pub fn messages(&mut self) -> Messages<'_, Self> {
+        Messages {
+            connection: self,
+            done: false,
+        }
+    }
+
+    /// Run the deserializer over the buffered bytes, skipping the first `skip` ones and reading at
+    /// most `limit` bytes
+    fn deserialize_from(
+        &mut self,
+        skip: usize,
+        limit: usize,
+    ) -> (Result<Incoming, Protocol::DeserializerError>, io::ReadStats) {
+        let mut buf = self.buffer.get_read();
+        skip_bytes(&mut buf, skip);
+        let mut buf = io::TrackedRead::new(io::LimitedRead::new(buf, limit));
+
+        let res = self.protocol.deserialize(&mut buf);
+        (res, buf.stats())
+    }
+}
+
+impl<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+where
+    Protocol: protocol::Protocol,
+    Outgoing: serde::Serialize,
+    OutBufferFactory: FnMut() -> OutBuffer,
+    OutBuffer: embedded_io::blocking::Write,
+{
+    /// Serialize `value` into a new buffer from the output buffer factory
+    pub fn serialize(
+        &mut self,
+        value: Outgoing,
+    ) -> Result<OutBuffer, SerializeError<Protocol::SerializerError, OutBuffer::Error>> {
+        let mut buf = (self.buffer_factory)();
+        self.serialize_into(&value, &mut buf).map(|()| buf)
+    }
+
+    /// Serialize `value` at the end of `writer`, without going through the output buffer
+    /// factory.
+    ///
+    /// This lets a single buffer (or the socket itself) be reused for every message.
+    pub fn serialize_into<W>(
+        &mut self,
+        value: &Outgoing,
+        writer: W,
+    ) -> Result<(), SerializeError<Protocol::SerializerError, W::Error>>
+    where
+        W: embedded_io::blocking::Write,
+    {
+        #[cfg(feature = "alloc")]
+        let packed;
+        let body = if !self.envelope.is_binary() {
+            Body::Message(value)
+        } else if matches!(self.framing, Framing::None | Framing::Lines) {
+            return Err(SerializeError::Unframed);
+        } else {
+            #[cfg(feature = "alloc")]
+            {
+                let mut serialized = alloc::vec::Vec::new();
+                self.protocol
+                    .serialize(value, &mut serialized)
+                    .map_err(SerializeError::Serialize)?;
+                packed = self
+                    .envelope
+                    .pack(serialized)
+                    .map_err(SerializeError::Serialize)?;
+                Body::Packed(&packed)
+            }
+            // Compression and encryption both need `alloc`
+            #[cfg(not(feature = "alloc"))]
+            unreachable!()
+        };
+
+        let mut writer = io::RecordingWrite::new(writer);
+        let res = match self.framing {
+            Framing::None => self.serialize_checked(body, &mut writer, false),
+            Framing::LengthPrefix(prefix) => {
+                // First pass only counts the bytes, so the prefix can be written before the
+                // message itself
+                let mut counter = io::CountingWrite::new();
+                self.write_body(body, &mut counter)
+                    .map_err(SerializeError::Serialize)?;
+                let mut header = [0u8; LengthPrefix::MAX_HEADER_LEN];
+                let len = counter.count() + self.envelope.checksum.map_or(0, Checksum::trailer_len);
+                let header_len = prefix.encode(len, &mut header).ok_or_else(|| {
+                    SerializeError::Serialize(
+                        <Protocol::SerializerError as serde::ser::Error>::custom(
+                            "message too long for the length prefix",
+                        ),
+                    )
+                })?;
+                if embedded_io::blocking::Write::write_all(&mut writer, &header[..header_len])
+                    .is_ok()
+                {
+                    self.serialize_checked(body, &mut writer, false)
+                } else {
+                    Ok(())
+                }
+            }
+            Framing::Cobs => {
+                let mut cobs = io::CobsWrite::new(&mut writer);
+                let res = self.serialize_checked(body, &mut cobs, false);
+                if res.is_ok() {
+                    // A failed write is kept by `writer`, and returned below
+                    let _ = cobs.finish();
+                }
+                res
+            }
+            Framing::Lines => self.serialize_checked(body, &mut writer, true).map(|()| {
+                // A failed write is kept by `writer`, and returned below
+                let _ = embedded_io::blocking::Write::write_all(&mut writer, b"\n");
+            }),
+        };
+
+        // Serializers usually bury the writer error in one of their own, the original one is
+        // more useful
+        match (writer.take_error(), res) {
+            (Some(e), _) => Err(SerializeError::Write(e)),
+            (None, res) => res.map_err(SerializeError::Serialize),
+        }
+    }
+
+    /// Write `body`, followed by its checksum trailer if there is one.
+    ///
+    /// A failed write of the trailer is kept by the [`io::RecordingWrite`] under `writer`.
+    fn serialize_checked<W>(
+        &mut self,
+        body: Body<'_, Outgoing>,
+        writer: W,
+        hex: bool,
+    ) -> Result<(), Protocol::SerializerError>
+    where
+        W: embedded_io::blocking::Write,
+    {
+        let Some(checksum) = self.envelope.checksum else {
+            return self.write_body(body, writer);
+        };
+        let mut writer = io::ChecksumWrite::new(writer, checksum);
+        self.write_body(body, &mut writer)?;
+        let (writer, crc) = writer.into_parts();
+        let _ = checksum.write_trailer(crc.finish(), hex, writer);
+        Ok(())
+    }
+
+    fn write_body<W>(
+        &mut self,
+        body: Body<'_, Outgoing>,
+        writer: W,
+    ) -> Result<(), Protocol::SerializerError>
+    where
+        W: embedded_io::blocking::Write,
+    {
+        match body {
+            Body::Message(value) => self.protocol.serialize(value, writer),
+            #[cfg(feature = "alloc")]
+            Body::Packed(bytes) => {
+                // A failed write is kept by the `io::RecordingWrite` under `writer`
+                let mut writer = writer;
+                let _ = writer.write_all(bytes);
+                Ok(())
             }
         }
     }
+
+    /// Serialize `value` at the start of `out`, returning the number of bytes written
+    pub fn serialize_to_slice(
+        &mut self,
+        value: &Outgoing,
+        out: &mut [u8],
+    ) -> Result<usize, SerializeError<Protocol::SerializerError, Overflow>> {
+        let mut writer = io::SliceWrite::new(out);
+        self.serialize_into(value, &mut writer)
+            .map(|()| writer.written())
+    }
 }
 
-#[cfg(feature = "alloc")]
-impl<
-        ProtocolDe,
-        ProtocolSer,
-        ProtocolDeFactory,
-        ProtocolSerFactory,
-        Message: serde::Serialize + serde::de::DeserializeOwned,
-        DeserializerError,
-        SerializerError,
-    >
-    Connection<
-        ProtocolDe,
-        ProtocolSer,
-        ProtocolDeFactory,
-        ProtocolSerFactory,
-        buffer::RingBuffer,
-        fn() -> Vec<u8>,
-        Vec<u8>,
-        core::convert::Infallible,
-        Message,
+/// What is written in a frame: the message, or the bytes it was already packed into
+enum Body<'a, T> {
+    Message(&'a T),
+    #[cfg(feature = "alloc")]
+    Packed(&'a [u8]),
+}
+
+impl<T> Clone for Body<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Body<'_, T> {}
+
+/// Iterator returned by [`Connection::messages`]
+pub struct Messages<'c, C> {
+    connection: &'c mut C,
+    done: bool,
+}
+
+impl<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer> Iterator
+    for Messages<
+        '_,
+        Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>,
     >
 where
-    ProtocolDeFactory: FnMut(
-        io::SignalDrop<dyn embedded_io::blocking::Read<Error = core::convert::Infallible>>,
-    ) -> ProtocolDe,
-    ProtocolSerFactory: FnMut(
-        io::SignalDrop<dyn embedded_io::blocking::Write<Error = core::convert::Infallible>>,
-    ) -> ProtocolSer,
-    for<'r> &'r mut ProtocolSer: serde::Serializer<Error = SerializerError>,
-    for<'r, 'de> &'r mut ProtocolDe: serde::Deserializer<'de, Error = DeserializerError>,
+    Protocol: protocol::Protocol,
+    Incoming: serde::de::DeserializeOwned,
+    InBuffer: buffer::Buffer,
+    OutBufferFactory: FnMut() -> OutBuffer,
 {
-    pub fn new_alloc(ser_factory: ProtocolSerFactory, de_factory: ProtocolDeFactory) -> Self {
-        Connection::new(
-            ser_factory,
-            de_factory,
-            || std::vec::Vec::<u8>::with_capacity(128),
-            buffer::RingBuffer::new(512),
+    type Item = Result<Incoming, DeserializeError<Protocol::DeserializerError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.connection.try_deserialize() {
+            Ok(message) => Some(Ok(message)),
+            Err(DeserializeError::Incomplete { .. }) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<C> core::iter::FusedIterator for Messages<'_, C> where Self: Iterator {}
+
+/// Read (and throw away) up to `count` bytes, returning how many bytes were actually available
+fn skip_bytes<R>(reader: &mut R, count: usize) -> usize
+where
+    R: embedded_io::blocking::Read<Error = core::convert::Infallible> + ?Sized,
+{
+    let mut scratch = [0u8; 64];
+    let mut skipped = 0;
+    while skipped < count {
+        let max = scratch.len().min(count - skipped);
+        match reader.read(&mut scratch[..max]) {
+            Ok(0) => break,
+            Ok(n) => skipped += n,
+            Err(e) => match e {},
+        }
+    }
+    skipped
+}
+
+#[cfg(feature = "alloc")]
+impl<Protocol, Message> Connection<Protocol, Message, Message> {
+    /// Create a connection with the same buffers as [`ConnectionBuilder::new`], sending and
+    /// receiving the same type of messages
+    pub fn new_alloc(protocol: Protocol) -> Self {
+        Self::new(
+            protocol,
+            alloc::vec::Vec::new,
+            buffer::RingBuffer::new(DEFAULT_BUFFER_SIZE),
         )
     }
 }
@@ -0,0 +1,67 @@
+/// Bounds on the bytes held by a [`Connection`](crate::Connection), to keep a peer from making it
+/// buffer without end.
+///
+/// The default has no limits at all: a [`RingBuffer`](crate::buffer::RingBuffer) grows as long
+/// as bytes are fed to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// The most bytes the input buffer may hold, `feed_bytes` doesn't take anything past it
+    pub max_buffered: Option<usize>,
+    /// The most bytes a single message may take, framing included
+    pub max_message: Option<usize>,
+    /// What happens to a message that crosses one of the limits
+    pub policy: OversizedPolicy,
+}
+
+/// What a [`Connection`](crate::Connection) does when a message can't fit in its [`Limits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizedPolicy {
+    /// Only refuse the bytes: once the buffer is full, `feed_bytes` takes nothing more and
+    /// deserializing reports an incomplete message, like a full fixed size buffer would.
+    ///
+    /// A message known to be longer than `max_message` is still reported as
+    /// [`DeserializeError::Oversized`](crate::DeserializeError::Oversized).
+    #[default]
+    Reject,
+    /// Report [`DeserializeError::Oversized`](crate::DeserializeError::Oversized) once the buffer
+    /// is full without holding a whole message, leaving the bytes in place
+    Error,
+    /// Report [`DeserializeError::Oversized`](crate::DeserializeError::Oversized) once, and drop
+    /// the message to carry on with the next one.
+    ///
+    /// When the end of the message isn't known (without a length prefix), every buffered byte is
+    /// dropped and the next bytes fed are expected to start a new message.
+    Resync,
+}
+
+impl Limits {
+    /// Whether a whole message of `len` bytes is too long
+    pub(crate) fn too_long(&self, len: usize) -> bool {
+        self.max_message.is_some_and(|max| len > max)
+    }
+
+    /// Whether an incomplete message, with `buffered` bytes of it received so far, will never fit
+    pub(crate) fn stuck(&self, buffered: usize) -> bool {
+        self.too_long(buffered)
+            || (self.policy != OversizedPolicy::Reject
+                && self.max_buffered.is_some_and(|max| buffered >= max))
+    }
+
+    /// The bytes to drop for a message that doesn't fit, as `(buffered, not fed yet)`.
+    ///
+    /// `len` is the length of the message when it is known, and `buffered` the number of bytes
+    /// in the buffer.
+    pub(crate) fn dropped(&self, len: Option<usize>, buffered: usize) -> (usize, usize) {
+        if self.policy != OversizedPolicy::Resync {
+            return (0, 0);
+        }
+        let len = len.unwrap_or(buffered);
+        (len.min(buffered), len.saturating_sub(buffered))
+    }
+
+    /// How many more bytes can be fed into a buffer holding `buffered` bytes
+    pub(crate) fn room(&self, buffered: usize) -> usize {
+        self.max_buffered
+            .map_or(usize::MAX, |max| max.saturating_sub(buffered))
+    }
+}
@@ -0,0 +1,484 @@
+//! The serde formats a [`Connection`](crate::Connection) can speak.
+//!
+//! The reader (or writer) given to a [`Protocol`] is only borrowed for a single
+//! (de)serialization, so nothing built around it can outlive the buffer it points into.
+//!
+//! Every ready-made protocol sits behind the cargo feature of the same name.
+
+use crate::DeserializeError;
+
+/// A serde format that can write messages into a byte sink and read them back from the bytes
+/// buffered by a [`Connection`](crate::Connection)
+pub trait Protocol {
+    type SerializerError: serde::ser::Error;
+    type DeserializerError: serde::de::Error;
+
+    fn serialize<T, W>(&mut self, value: &T, writer: W) -> Result<(), Self::SerializerError>
+    where
+        T: serde::Serialize + ?Sized,
+        W: embedded_io::blocking::Write;
+
+    /// Deserialize a single value from `reader`.
+    ///
+    /// Without framing, the reader holds every buffered byte: the deserializer should read as
+    /// little as possible past the end of the value, since anything read is considered consumed.
+    fn deserialize<T, R>(&mut self, reader: R) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+        R: embedded_io::blocking::Read<Error = core::convert::Infallible>;
+
+    /// Deserialize the single value held by a line, see [`Framing::Lines`](crate::Framing::Lines):
+    /// nothing but whitespace may follow it.
+    ///
+    /// The default checks what the deserializer left of `line`, which misses anything it read
+    /// ahead of the value.
+    fn deserialize_line<T>(&mut self, line: &[u8]) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut rest = line;
+        let value = self.deserialize(&mut rest)?;
+        if rest.iter().all(u8::is_ascii_whitespace) {
+            Ok(value)
+        } else {
+            Err(serde::de::Error::custom("trailing bytes after the message"))
+        }
+    }
+
+    /// The minimum number of bytes a message cut short still needs, when the deserializer ran out
+    /// of bytes on a read that was `short_by` bytes short. `buffered` holds every byte received
+    /// so far, starting with the message.
+    ///
+    /// Without framing, the connection doesn't run the deserializer again before that many bytes
+    /// are fed, instead of starting over on every new byte. This is only right for deserializers
+    /// that never ask for more bytes than the message holds: the default gives no hint.
+    fn missing_bytes(&self, buffered: &[u8], short_by: usize) -> Option<usize> {
+        let _ = (buffered, short_by);
+        None
+    }
+}
+
+/// A [`Protocol`] able to deserialize messages that borrow from the buffered bytes, see
+/// [`Connection::try_deserialize_borrowed`](crate::Connection::try_deserialize_borrowed)
+pub trait BorrowingProtocol: Protocol {
+    /// Deserialize a single value from the start of `bytes`, returning it along with the number
+    /// of bytes it took.
+    ///
+    /// Without framing, `bytes` holds every buffered byte and may end in the middle of the value:
+    /// this should be reported as [`DeserializeError::Incomplete`].
+    fn deserialize_borrowed<'de, T>(
+        &mut self,
+        bytes: &'de [u8],
+    ) -> Result<(T, usize), DeserializeError<Self::DeserializerError>>
+    where
+        T: serde::Deserialize<'de>;
+}
+
+/// JSON, using `serde_json`.
+///
+/// Nothing tells how long a JSON value is before its end is reached, so there is no
+/// [`Protocol::missing_bytes`] hint: without framing, a message that isn't whole yet is parsed
+/// again from its start every time bytes are fed, which grows quadratic with its size. Large
+/// messages are better sent with [`Framing::Lines`](crate::Framing::Lines) or a length prefix.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Protocol for Json {
+    type SerializerError = serde_json::Error;
+    type DeserializerError = serde_json::Error;
+
+    fn serialize<T, W>(&mut self, value: &T, writer: W) -> Result<(), Self::SerializerError>
+    where
+        T: serde::Serialize + ?Sized,
+        W: embedded_io::blocking::Write,
+    {
+        serde_json::to_writer(embedded_io::adapters::ToStd::new(writer), value)
+    }
+
+    fn deserialize<T, R>(&mut self, reader: R) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+        R: embedded_io::blocking::Read<Error = core::convert::Infallible>,
+    {
+        // `serde_json::from_reader` would check that nothing follows the value, which would eat
+        // the next message
+        let mut deserializer =
+            serde_json::Deserializer::from_reader(embedded_io::adapters::ToStd::new(reader));
+        T::deserialize(&mut deserializer)
+    }
+
+    fn deserialize_line<T>(&mut self, line: &[u8]) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut deserializer = serde_json::Deserializer::from_slice(line);
+        let value = T::deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        Ok(value)
+    }
+}
+
+/// Strings containing escape sequences can't be borrowed, use a `Cow<str>` for those
+#[cfg(feature = "json")]
+impl BorrowingProtocol for Json {
+    fn deserialize_borrowed<'de, T>(
+        &mut self,
+        bytes: &'de [u8],
+    ) -> Result<(T, usize), DeserializeError<Self::DeserializerError>>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        let mut stream = serde_json::Deserializer::from_slice(bytes).into_iter();
+        match stream.next() {
+            Some(Ok(value)) => Ok((value, stream.byte_offset())),
+            // A value cut short doesn't always give an EOF error (like a lone `-`), so anything
+            // failing on the last byte is considered incomplete, as with the buffer reader
+            Some(Err(e)) if !e.is_eof() && !json_error_at_end(bytes, &e) => {
+                Err(DeserializeError::Invalid(e))
+            }
+            // Either an unterminated value, or nothing but whitespace
+            _ => Err(DeserializeError::Incomplete { needed: None }),
+        }
+    }
+}
+
+/// Whether `error` happened on the last byte of `bytes`
+#[cfg(feature = "json")]
+fn json_error_at_end(bytes: &[u8], error: &serde_json::Error) -> bool {
+    let line_start: usize = bytes
+        .split_inclusive(|&byte| byte == b'\n')
+        .take(error.line().saturating_sub(1))
+        .map(<[u8]>::len)
+        .sum();
+    line_start + error.column() >= bytes.len()
+}
+
+/// MessagePack, using `rmp-serde`
+#[cfg(feature = "messagepack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "messagepack")]
+impl Protocol for MessagePack {
+    type SerializerError = rmp_serde::encode::Error;
+    type DeserializerError = rmp_serde::decode::Error;
+
+    fn serialize<T, W>(&mut self, value: &T, writer: W) -> Result<(), Self::SerializerError>
+    where
+        T: serde::Serialize + ?Sized,
+        W: embedded_io::blocking::Write,
+    {
+        let mut writer = embedded_io::adapters::ToStd::new(writer);
+        rmp_serde::encode::write(&mut writer, value)
+    }
+
+    fn deserialize<T, R>(&mut self, reader: R) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+        R: embedded_io::blocking::Read<Error = core::convert::Infallible>,
+    {
+        rmp_serde::from_read(embedded_io::adapters::ToStd::new(reader))
+    }
+
+    /// Strings and byte arrays are read in growing chunks, never past their end. The elements of
+    /// an array or a map are read one at a time though, so the buffered bytes are also measured,
+    /// counting at least a byte for every element not received yet: each attempt waits for a
+    /// share of what is left, instead of a single element
+    fn missing_bytes(&self, buffered: &[u8], short_by: usize) -> Option<usize> {
+        let at_least = match messagepack_value_len(buffered) {
+            Ok(ValueLen::AtLeast(len)) => len.saturating_sub(buffered.len()),
+            Ok(ValueLen::Complete(_)) | Err(()) => 0,
+        };
+        Some(short_by.max(at_least))
+    }
+}
+
+#[cfg(feature = "messagepack")]
+impl BorrowingProtocol for MessagePack {
+    fn deserialize_borrowed<'de, T>(
+        &mut self,
+        bytes: &'de [u8],
+    ) -> Result<(T, usize), DeserializeError<Self::DeserializerError>>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        // `rmp-serde` doesn't tell how much of a slice it read, so the value is measured first
+        let len = match messagepack_value_len(bytes) {
+            Ok(ValueLen::Complete(len)) => len,
+            Ok(ValueLen::AtLeast(len)) => {
+                return Err(DeserializeError::Incomplete {
+                    needed: Some(len - bytes.len()),
+                })
+            }
+            Err(()) => {
+                return Err(DeserializeError::Invalid(serde::de::Error::custom(
+                    "invalid MessagePack marker",
+                )))
+            }
+        };
+        rmp_serde::from_slice(&bytes[..len])
+            .map(|value| (value, len))
+            .map_err(DeserializeError::Invalid)
+    }
+}
+
+/// The length of a MessagePack value, as far as the bytes at hand tell
+#[cfg(feature = "messagepack")]
+enum ValueLen {
+    Complete(usize),
+    /// The bytes end before the value does, which takes at least that many bytes
+    AtLeast(usize),
+}
+
+/// Find the length of the MessagePack value at the start of `bytes`, without decoding it.
+///
+/// Returns `Err` if it can't be a MessagePack value, or if its length doesn't fit in a `usize`.
+#[cfg(feature = "messagepack")]
+fn messagepack_value_len(bytes: &[u8]) -> Result<ValueLen, ()> {
+    // Reads a big endian length of `width` bytes at `at`, as a `u64` so no arithmetic on it can
+    // overflow
+    let read_len = |at: usize, width: usize| -> Option<u64> {
+        let field = bytes.get(at..)?.get(..width)?;
+        Some(
+            field
+                .iter()
+                .fold(0, |len, &byte| (len << 8) | u64::from(byte)),
+        )
+    };
+    let add = |a: usize, b: u64| usize::try_from(b).ok().and_then(|b| a.checked_add(b));
+
+    let mut pos: usize = 0;
+    // Number of values left to skip, arrays and maps add their elements to it
+    let mut pending: usize = 1;
+    while pending > 0 {
+        // Every value left takes at least its marker
+        let Some(&marker) = bytes.get(pos) else {
+            return pos.checked_add(pending).map(ValueLen::AtLeast).ok_or(());
+        };
+        pending -= 1;
+        pos += 1;
+        let at_least = |width: u64| {
+            add(pos, width)
+                .and_then(|len| len.checked_add(pending))
+                .map(ValueLen::AtLeast)
+                .ok_or(())
+        };
+        // (bytes following the marker, number of nested values)
+        let (payload, nested): (u64, u64) = match marker {
+            0x00..=0x7f | 0xe0..=0xff | 0xc0 | 0xc2 | 0xc3 => (0, 0),
+            0x80..=0x8f => (0, 2 * u64::from(marker & 0x0f)),
+            0x90..=0x9f => (0, u64::from(marker & 0x0f)),
+            0xa0..=0xbf => (u64::from(marker & 0x1f), 0),
+            0xc4 | 0xd9 => {
+                let Some(len) = read_len(pos, 1) else {
+                    return at_least(1);
+                };
+                (1 + len, 0)
+            }
+            0xc5 | 0xda => {
+                let Some(len) = read_len(pos, 2) else {
+                    return at_least(2);
+                };
+                (2 + len, 0)
+            }
+            0xc6 | 0xdb => {
+                let Some(len) = read_len(pos, 4) else {
+                    return at_least(4);
+                };
+                (4 + len, 0)
+            }
+            // Extensions have a type byte after their length
+            0xc7 => {
+                let Some(len) = read_len(pos, 1) else {
+                    return at_least(1);
+                };
+                (2 + len, 0)
+            }
+            0xc8 => {
+                let Some(len) = read_len(pos, 2) else {
+                    return at_least(2);
+                };
+                (3 + len, 0)
+            }
+            0xc9 => {
+                let Some(len) = read_len(pos, 4) else {
+                    return at_least(4);
+                };
+                (5 + len, 0)
+            }
+            0xcc | 0xd0 => (1, 0),
+            0xcd | 0xd1 => (2, 0),
+            0xca | 0xce | 0xd2 => (4, 0),
+            0xcb | 0xcf | 0xd3 => (8, 0),
+            0xd4 => (2, 0),
+            0xd5 => (3, 0),
+            0xd6 => (5, 0),
+            0xd7 => (9, 0),
+            0xd8 => (17, 0),
+            0xdc | 0xde => {
+                let Some(len) = read_len(pos, 2) else {
+                    return at_least(2);
+                };
+                (2, if marker == 0xde { 2 * len } else { len })
+            }
+            0xdd | 0xdf => {
+                let Some(len) = read_len(pos, 4) else {
+                    return at_least(4);
+                };
+                (4, if marker == 0xdf { 2 * len } else { len })
+            }
+            0xc1 => return Err(()),
+        };
+        // A length past `usize::MAX` can't be buffered, whatever follows
+        pos = add(pos, payload).ok_or(())?;
+        pending = add(pending, nested).ok_or(())?;
+    }
+
+    Ok(if pos <= bytes.len() {
+        ValueLen::Complete(pos)
+    } else {
+        ValueLen::AtLeast(pos)
+    })
+}
+
+/// Postcard, using `postcard`. Doesn't need `std` nor `alloc`.
+///
+/// Strings and byte arrays are copied into a `SCRATCH` bytes long buffer on the stack while a
+/// message is deserialized, it needs to be big enough to hold all of them.
+#[cfg(feature = "postcard")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postcard<const SCRATCH: usize = 256>;
+
+#[cfg(feature = "postcard")]
+impl<const SCRATCH: usize> Protocol for Postcard<SCRATCH> {
+    type SerializerError = postcard::Error;
+    type DeserializerError = postcard::Error;
+
+    fn serialize<T, W>(&mut self, value: &T, writer: W) -> Result<(), Self::SerializerError>
+    where
+        T: serde::Serialize + ?Sized,
+        W: embedded_io::blocking::Write,
+    {
+        postcard::to_eio(value, writer).map(|_| ())
+    }
+
+    fn deserialize<T, R>(&mut self, reader: R) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+        R: embedded_io::blocking::Read<Error = core::convert::Infallible>,
+    {
+        let mut scratch = [0u8; SCRATCH];
+        postcard::from_eio((reader, &mut scratch)).map(|(value, _)| value)
+    }
+
+    /// Values are read with exactly the bytes they need
+    fn missing_bytes(&self, _: &[u8], short_by: usize) -> Option<usize> {
+        Some(short_by)
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl<const SCRATCH: usize> BorrowingProtocol for Postcard<SCRATCH> {
+    fn deserialize_borrowed<'de, T>(
+        &mut self,
+        bytes: &'de [u8],
+    ) -> Result<(T, usize), DeserializeError<Self::DeserializerError>>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        match postcard::take_from_bytes(bytes) {
+            Ok((value, rest)) => Ok((value, bytes.len() - rest.len())),
+            Err(postcard::Error::DeserializeUnexpectedEnd) => {
+                Err(DeserializeError::Incomplete { needed: None })
+            }
+            Err(e) => Err(DeserializeError::Invalid(e)),
+        }
+    }
+}
+
+/// CBOR, using `ciborium`
+///
+/// `ciborium` can't borrow from its input, so this isn't a [`BorrowingProtocol`].
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Protocol for Cbor {
+    type SerializerError = ciborium::ser::Error<std::io::Error>;
+    type DeserializerError = ciborium::de::Error<std::io::Error>;
+
+    fn serialize<T, W>(&mut self, value: &T, writer: W) -> Result<(), Self::SerializerError>
+    where
+        T: serde::Serialize + ?Sized,
+        W: embedded_io::blocking::Write,
+    {
+        ciborium::ser::into_writer(value, embedded_io::adapters::ToStd::new(writer))
+    }
+
+    fn deserialize<T, R>(&mut self, reader: R) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+        R: embedded_io::blocking::Read<Error = core::convert::Infallible>,
+    {
+        ciborium::de::from_reader(embedded_io::adapters::ToStd::new(reader))
+    }
+
+    /// Strings and byte arrays are read in chunks, never past their end
+    fn missing_bytes(&self, _: &[u8], short_by: usize) -> Option<usize> {
+        Some(short_by)
+    }
+}
+
+/// RON, using `ron`
+///
+/// RON reads everything it is given before parsing it, so it needs a framing other than
+/// [`Framing::None`](crate::Framing::None) to tell messages apart.
+#[cfg(feature = "ron")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ron;
+
+#[cfg(feature = "ron")]
+impl Protocol for Ron {
+    type SerializerError = ron::Error;
+    type DeserializerError = ron::Error;
+
+    fn serialize<T, W>(&mut self, value: &T, writer: W) -> Result<(), Self::SerializerError>
+    where
+        T: serde::Serialize + ?Sized,
+        W: embedded_io::blocking::Write,
+    {
+        ron::ser::to_writer(embedded_io::adapters::ToStd::new(writer), value)
+    }
+
+    fn deserialize<T, R>(&mut self, reader: R) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+        R: embedded_io::blocking::Read<Error = core::convert::Infallible>,
+    {
+        ron::de::from_reader(embedded_io::adapters::ToStd::new(reader)).map_err(|e| e.code)
+    }
+}
+
+/// The whole frame has to be a single value
+#[cfg(feature = "ron")]
+impl BorrowingProtocol for Ron {
+    fn deserialize_borrowed<'de, T>(
+        &mut self,
+        bytes: &'de [u8],
+    ) -> Result<(T, usize), DeserializeError<Self::DeserializerError>>
+    where
+        T: serde::Deserialize<'de>,
+    {
+        let mut deserializer = ron::de::Deserializer::from_bytes(bytes)
+            .map_err(|e| DeserializeError::Invalid(e.code))?;
+        T::deserialize(&mut deserializer)
+            .and_then(|value| deserializer.end().map(|()| (value, bytes.len())))
+            .map_err(|e| match e {
+                ron::Error::Eof => DeserializeError::Incomplete { needed: None },
+                e => DeserializeError::Invalid(e),
+            })
+    }
+}
@@ -0,0 +1,158 @@
+//! A [`Connection`] bound to a blocking `std` transport, like a `TcpStream` or a `UnixStream`.
+
+use std::io::{ErrorKind, Read, Write};
+use std::vec::Vec;
+
+use crate::{buffer, protocol, Connection, DeserializeError};
+
+/// The reason why a [`StreamConnection`] couldn't send or receive a message, which is the
+/// crate-wide [`Error`](crate::Error)
+pub type StreamError<SerializerError, DeserializerError> =
+    crate::Error<SerializerError, DeserializerError>;
+
+type Error<Protocol> = StreamError<
+    <Protocol as protocol::Protocol>::SerializerError,
+    <Protocol as protocol::Protocol>::DeserializerError,
+>;
+
+/// Sends `Outgoing` messages and receives `Incoming` ones over `Transport`, feeding the
+/// [`Connection`] buffer as needed
+pub struct StreamConnection<
+    Transport,
+    Protocol,
+    Incoming,
+    Outgoing = Incoming,
+    InBuffer = buffer::RingBuffer,
+    OutBufferFactory = fn() -> Vec<u8>,
+    OutBuffer = Vec<u8>,
+> {
+    transport: Transport,
+    connection: Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>,
+    /// Bytes read from the transport that didn't fit in the input buffer
+    unfed: Vec<u8>,
+    /// Serialized bytes the transport didn't accept yet
+    unsent: Vec<u8>,
+}
+
+impl<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    StreamConnection<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+where
+    Transport: Read + Write,
+    Protocol: protocol::Protocol,
+    Incoming: serde::de::DeserializeOwned,
+    Outgoing: serde::Serialize,
+    InBuffer: buffer::Buffer,
+    OutBufferFactory: FnMut() -> OutBuffer,
+    OutBuffer: embedded_io::blocking::Write,
+{
+    /// Serialize `message` and write it to the transport.
+    ///
+    /// If the transport returns [`ErrorKind::WouldBlock`], the message is still queued: the rest
+    /// of it is written by the next call to [`StreamConnection::send`] or
+    /// [`StreamConnection::flush`].
+    pub fn send(&mut self, message: &Outgoing) -> Result<(), Error<Protocol>> {
+        // Serialized straight after the queued bytes, a failure must not leave half a message
+        let queued = self.unsent.len();
+        if let Err(e) = self.connection.serialize_into(message, &mut self.unsent) {
+            self.unsent.truncate(queued);
+            return Err(e.into());
+        }
+        self.flush()
+    }
+
+    /// Write every queued byte to the transport, then flush it
+    pub fn flush(&mut self) -> Result<(), Error<Protocol>> {
+        let mut written = 0;
+        let res = loop {
+            if written == self.unsent.len() {
+                break self.transport.flush();
+            }
+            match self.transport.write(&self.unsent[written..]) {
+                Ok(0) => break Err(ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.unsent.drain(..written);
+        res.map_err(StreamError::Io)
+    }
+
+    /// Return the next message, reading from the transport until a whole one is buffered.
+    ///
+    /// Messages already buffered are returned without touching the transport.
+    pub fn recv(&mut self) -> Result<Incoming, Error<Protocol>> {
+        loop {
+            match self.connection.try_deserialize() {
+                Ok(message) => return Ok(message),
+                Err(DeserializeError::Incomplete { .. }) => self.fill()?,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Drop the message that [`StreamConnection::recv`] reported as invalid, see
+    /// [`Connection::skip_to_next_frame`]
+    pub fn skip_to_next_frame(&mut self) -> usize {
+        self.connection.skip_to_next_frame()
+    }
+
+    /// Feed the connection with more bytes, reading from the transport if needed
+    fn fill(&mut self) -> Result<(), Error<Protocol>> {
+        if self.unfed.is_empty() {
+            let mut scratch = [0u8; crate::DEFAULT_BUFFER_SIZE];
+            let read = loop {
+                match self.transport.read(&mut scratch) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(StreamError::Io(e)),
+                }
+            };
+            if read == 0 {
+                return Err(if self.connection.buffered() == 0 {
+                    StreamError::Closed
+                } else {
+                    StreamError::Io(ErrorKind::UnexpectedEof.into())
+                });
+            }
+            self.unfed.extend_from_slice(&scratch[..read]);
+        }
+
+        let fed = self.connection.feed_bytes(&self.unfed);
+        if fed == 0 {
+            return Err(StreamError::BufferFull);
+        }
+        self.unfed.drain(..fed);
+        Ok(())
+    }
+}
+
+impl<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+    StreamConnection<Transport, Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>
+{
+    pub fn new(
+        transport: Transport,
+        connection: Connection<Protocol, Incoming, Outgoing, InBuffer, OutBufferFactory, OutBuffer>,
+    ) -> Self {
+        Self {
+            transport,
+            connection,
+            unfed: Vec::new(),
+            unsent: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &Transport {
+        &self.transport
+    }
+
+    /// Reading from (or writing to) the transport directly will desync the connection
+    pub fn get_mut(&mut self) -> &mut Transport {
+        &mut self.transport
+    }
+
+    /// Give back the transport, dropping any buffered bytes
+    pub fn into_inner(self) -> Transport {
+        self.transport
+    }
+}
@@ -0,0 +1,124 @@
+extern crate connecteer_translation;
+extern crate serde;
+
+use futures::{SinkExt, StreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+use connecteer_translation::{
+    protocol::MessagePack, stream::StreamError, AsyncConnection, Connection, Framing, LengthPrefix,
+    Limits, OversizedPolicy,
+};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let messages = (0..100)
+        .map(|i| Something {
+            foo: "Hello".repeat(i % 7),
+            bar: i * 1000,
+        })
+        .collect::<Vec<_>>();
+
+    // TCP loopback, with a task on the other side echoing every message back
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let echo = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let server =
+            AsyncConnection::<_, _, Something>::new(socket, Connection::new_alloc(MessagePack));
+        let (mut sink, mut stream) = server.split();
+        while let Some(message) = stream.next().await {
+            sink.send(message.unwrap()).await.unwrap();
+        }
+    });
+    let mut client = AsyncConnection::new(
+        TcpStream::connect(addr).await.unwrap(),
+        Connection::new_alloc(MessagePack),
+    );
+    for message in &messages {
+        client.send(message.clone()).await.unwrap();
+        assert_eq!(&client.next().await.unwrap().unwrap(), message);
+    }
+
+    // Everything is queued before being flushed at once, and read back after the socket closes
+    for message in &messages {
+        client.feed(message.clone()).await.unwrap();
+    }
+    client.flush().await.unwrap();
+    let received = client
+        .by_ref()
+        .take(messages.len())
+        .map(Result::unwrap)
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(received, messages);
+    client.close().await.unwrap();
+    echo.await.unwrap();
+
+    // An in memory pipe too small to hold a message, cut in the middle of the second one
+    let (a, b) = tokio::io::duplex(3);
+    let framing = Framing::LengthPrefix(LengthPrefix::Varint);
+    let mut a = AsyncConnection::new(a, Connection::new_alloc(MessagePack).with_framing(framing));
+    let mut b = AsyncConnection::<_, _, Something>::new(
+        b,
+        Connection::new_alloc(MessagePack).with_framing(framing),
+    );
+    let first = messages[3].clone();
+    let writer = tokio::spawn(async move {
+        a.send(first).await.unwrap();
+        // The header announces 42 bytes, but only 2 of them follow
+        a.into_inner().write_all(&[42, 1, 2]).await.unwrap();
+    });
+    assert_eq!(b.next().await.unwrap().unwrap(), messages[3]);
+    writer.await.unwrap();
+    assert!(matches!(
+        b.next().await,
+        Some(Err(StreamError::Io(e))) if e.kind() == std::io::ErrorKind::UnexpectedEof
+    ));
+    assert!(b.next().await.is_none());
+
+    // A corrupt message is reported once, then the stream goes on with the next one
+    let (a, b) = tokio::io::duplex(1024);
+    let mut a = AsyncConnection::new(a, Connection::new_alloc(MessagePack).with_framing(framing));
+    let mut b = AsyncConnection::<_, _, Something>::new(
+        b,
+        Connection::new_alloc(MessagePack).with_framing(framing),
+    );
+    // 0xc1 is never used by MessagePack
+    a.get_mut().write_all(&[1, 0xc1]).await.unwrap();
+    a.send(messages[5].clone()).await.unwrap();
+    assert!(matches!(
+        b.next().await,
+        Some(Err(StreamError::Deserialize(_)))
+    ));
+    assert_eq!(b.next().await.unwrap().unwrap(), messages[5]);
+
+    // So is a message that fills the buffer, even when the limits leave it in place
+    let limits = Limits {
+        max_message: None,
+        max_buffered: Some(32),
+        policy: OversizedPolicy::Error,
+    };
+    let (a, b) = tokio::io::duplex(1024);
+    let mut a = AsyncConnection::new(a, Connection::new_alloc(MessagePack).with_framing(framing));
+    let mut b = AsyncConnection::<_, _, Something>::new(
+        b,
+        Connection::new_alloc(MessagePack)
+            .with_framing(framing)
+            .with_limits(limits),
+    );
+    let large = Something {
+        foo: "Hello".repeat(20),
+        bar: 0,
+    };
+    a.feed(large).await.unwrap();
+    a.send(messages[5].clone()).await.unwrap();
+    assert!(matches!(b.next().await, Some(Err(StreamError::Oversized))));
+    assert_eq!(b.next().await.unwrap().unwrap(), messages[5]);
+}
+
+#[derive(PartialEq, serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct Something {
+    foo: String,
+    bar: usize,
+}
@@ -2,13 +2,13 @@ extern crate connecteer_translation;
 extern crate serde;
 extern crate serde_json;
 
-use connecteer_translation::{embedded_io::adapters::ToStd, Connection};
+use connecteer_translation::{
+    protocol::Json, Compression, Connection, Framing, JsonConnection, LengthPrefix, Limits,
+    OversizedPolicy,
+};
 
 fn main() {
-    let mut connection = Connection::new_alloc(
-        |v| serde_json::Serializer::new(ToStd::new(v)),
-        |v| serde_json::Deserializer::from_reader(ToStd::new(v)),
-    );
+    let mut connection: JsonConnection<serde_json::Value> = Connection::new_alloc(Json);
 
     let before = serde_json::json!({ /* Packet here */ });
 
@@ -16,4 +16,143 @@ fn main() {
     connection.feed_bytes(&val);
 
     assert_eq!(before, connection.try_deserialize().unwrap());
+
+    let mut framed =
+        Connection::new_alloc(Json).with_framing(Framing::LengthPrefix(LengthPrefix::Varint));
+
+    // A bare number isn't self-delimiting, the prefix is what tells where it ends
+    let before = serde_json::json!(1234);
+    let val = framed.serialize(before.clone()).unwrap();
+    assert_eq!(val[0] as usize, val.len() - 1);
+
+    framed.feed_bytes(&val[..val.len() - 1]);
+    assert!(matches!(
+        framed.try_deserialize(),
+        Err(connecteer_translation::DeserializeError::Incomplete { needed: Some(1) })
+    ));
+    framed.feed_bytes(&val[val.len() - 1..]);
+    assert_eq!(before, framed.try_deserialize().unwrap());
+
+    // Two messages sent back to back must come out one after the other, wherever the bytes were
+    // split when they were received
+    for framing in [
+        Framing::None,
+        Framing::LengthPrefix(LengthPrefix::U16Be),
+        Framing::Lines,
+    ] {
+        let new_connection = || Connection::new_alloc(Json).with_framing(framing);
+        let messages = [
+            serde_json::json!({ "id": 1, "name": "first" }),
+            serde_json::json!({ "id": 2, "tags": ["a", "b"] }),
+        ];
+        let mut bytes = Vec::new();
+        for message in &messages {
+            bytes.extend(new_connection().serialize(message.clone()).unwrap());
+        }
+        for split in 0..=bytes.len() {
+            let mut connection = new_connection();
+            let mut received = Vec::new();
+            for chunk in [&bytes[..split], &bytes[split..]] {
+                connection.feed_bytes(chunk);
+                received.extend(connection.messages().map(Result::unwrap));
+            }
+            assert_eq!(received, messages, "{framing:?}, split at byte {split}");
+        }
+    }
+
+    // With JSON Lines, a bad line can be skipped without losing the ones after it
+    let mut lines: JsonConnection<serde_json::Value> =
+        Connection::new_alloc(Json).with_framing(Framing::Lines);
+    let val = lines.serialize(serde_json::json!([1, 2])).unwrap();
+    assert_eq!(val, b"[1,2]\n");
+
+    lines.feed_bytes(b"\n{\"broken\n");
+    lines.feed_bytes(&val);
+    assert!(matches!(
+        lines.try_deserialize(),
+        Err(connecteer_translation::DeserializeError::Invalid(_))
+    ));
+    // The empty line before the bad one goes with it
+    assert_eq!(lines.skip_to_next_frame(), 10);
+    assert_eq!(serde_json::json!([1, 2]), lines.try_deserialize().unwrap());
+
+    // A line holds a single message, anything after it makes the line invalid
+    for line in [
+        &b"{\"a\":\"x\"} {\"a\":\"y\"}\n"[..],
+        b"[1] trailing junk\n",
+    ] {
+        lines.feed_bytes(line);
+        lines.feed_bytes(&val);
+        assert!(matches!(
+            lines.try_deserialize(),
+            Err(connecteer_translation::DeserializeError::Invalid(_))
+        ));
+        assert_eq!(lines.skip_to_next_frame(), line.len());
+        assert!(matches!(
+            lines.try_deserialize_borrowed::<serde_json::Value>(),
+            Ok(message) if *message == serde_json::json!([1, 2])
+        ));
+
+        lines.feed_bytes(line);
+        assert!(matches!(
+            lines.try_deserialize_borrowed::<serde_json::Value>(),
+            Err(connecteer_translation::DeserializeError::Invalid(_))
+        ));
+        assert_eq!(lines.skip_to_next_frame(), line.len());
+    }
+    lines.feed_bytes(b"[3]  \r\n");
+    assert_eq!(serde_json::json!([3]), lines.try_deserialize().unwrap());
+
+    // Repetitive payloads shrink a lot once compressed, small ones are sent as they are
+    let mut compressed: JsonConnection<serde_json::Value> = Connection::new_alloc(Json)
+        .with_framing(Framing::LengthPrefix(LengthPrefix::U32Be))
+        .with_compression(Compression::Lz4 { threshold: 64 });
+    let readings = serde_json::json!(vec![
+        serde_json::json!({ "sensor": "temperature", "value": 21 });
+        50
+    ]);
+    let small = serde_json::json!({ "sensor": "humidity" });
+    let mut bytes = compressed.serialize(readings.clone()).unwrap();
+    assert!(bytes.len() < serde_json::to_vec(&readings).unwrap().len() / 10);
+    bytes.extend(compressed.serialize(small.clone()).unwrap());
+
+    // Compressed bytes can hold newlines, so they can't be sent as JSON Lines
+    let mut unframed = Connection::new_alloc(Json)
+        .with_framing(Framing::Lines)
+        .with_compression(Compression::Lz4 { threshold: 64 });
+    assert!(matches!(
+        unframed.serialize(small.clone()),
+        Err(connecteer_translation::SerializeError::Unframed)
+    ));
+    unframed.feed_bytes(&bytes);
+    assert!(matches!(
+        unframed.try_deserialize(),
+        Err(connecteer_translation::DeserializeError::Unframed)
+    ));
+
+    // Still only deserialized once the whole frame is received
+    compressed.feed_bytes(&bytes[..bytes.len() - 1]);
+    assert_eq!(readings, compressed.try_deserialize().unwrap());
+    assert!(matches!(
+        compressed.try_deserialize(),
+        Err(connecteer_translation::DeserializeError::Incomplete { needed: Some(1) })
+    ));
+    compressed.feed_bytes(&bytes[bytes.len() - 1..]);
+    assert_eq!(small, compressed.try_deserialize().unwrap());
+
+    // A small frame that would decompress past the limits is dropped without being decompressed
+    let mut limited = compressed.with_limits(Limits {
+        max_message: Some(1000),
+        max_buffered: None,
+        policy: OversizedPolicy::Resync,
+    });
+    let mut bytes = limited.serialize(readings).unwrap();
+    assert!(bytes.len() < 1000);
+    bytes.extend(limited.serialize(small.clone()).unwrap());
+    limited.feed_bytes(&bytes);
+    assert!(matches!(
+        limited.try_deserialize(),
+        Err(connecteer_translation::DeserializeError::Oversized)
+    ));
+    assert_eq!(small, limited.try_deserialize().unwrap());
 }
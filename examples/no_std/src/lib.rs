@@ -0,0 +1,15 @@
+//! This crate only exists to check that `connecteer-translation` builds without `std` or `alloc`.
+//!
+//! Features are unified across the workspace, so it needs to be built on its own:
+//! `cargo build -p no_std`
+#![no_std]
+
+use connecteer_translation::buffer::{Buffer, StaticRingBuffer};
+use connecteer_translation::embedded_io::blocking::Read;
+
+pub fn round_trip(buffer: &mut StaticRingBuffer<64>, bytes: &[u8], out: &mut [u8]) -> usize {
+    let fed = buffer.feed_bytes(bytes);
+    let read = buffer.get_read().read(&mut out[..fed]).unwrap_or(0);
+    buffer.discard_read_bytes();
+    read
+}
@@ -0,0 +1,64 @@
+//! Feeds, reads and discards bytes through the ring buffers until they wrap around many times,
+//! checking them against a `VecDeque`.
+//!
+//! It is small enough to be run under Miri as well: `cargo +nightly miri run -p ring_buffers`
+extern crate connecteer_translation;
+
+use std::collections::VecDeque;
+
+use connecteer_translation::{
+    buffer::{Buffer, RingBuffer, StaticRingBuffer},
+    embedded_io::blocking::Read,
+};
+
+fn main() {
+    check(StaticRingBuffer::<7>::new());
+    check(StaticRingBuffer::<16>::new());
+    check(RingBuffer::new(4));
+}
+
+fn check<B: Buffer>(mut buffer: B) {
+    let mut model = VecDeque::<u8>::new();
+    let mut next = 0u8;
+    for step in 0..200 {
+        // Whatever fits in the buffer is taken, in order
+        let chunk: Vec<u8> = (0..step * 5 % 11)
+            .map(|i| next.wrapping_add(i as u8))
+            .collect();
+        let fed = buffer.feed_bytes(&chunk);
+        assert!(fed <= chunk.len());
+        model.extend(&chunk[..fed]);
+        next = next.wrapping_add(fed as u8);
+        assert_eq!(buffer.len(), model.len());
+
+        // Reads go across the wrap point, and only count once they are discarded
+        let mut out = vec![0; step * 3 % 9];
+        let read = read_all(&mut buffer.get_read(), &mut out);
+        assert_eq!(read, out.len().min(model.len()));
+        assert!(out[..read].iter().eq(model.iter().take(read)));
+        if step % 3 == 0 {
+            buffer.keep_read_bytes();
+        } else {
+            buffer.discard_read_bytes();
+            model.drain(..read);
+        }
+        assert_eq!(buffer.len(), model.len());
+
+        if step % 4 == 0 {
+            assert!(buffer.contiguous().iter().eq(model.iter()));
+        }
+    }
+}
+
+/// Read until `out` is full or the buffer is empty
+fn read_all(reader: &mut impl Read<Error = std::convert::Infallible>, out: &mut [u8]) -> usize {
+    let mut read = 0;
+    while read < out.len() {
+        match reader.read(&mut out[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) => match e {},
+        }
+    }
+    read
+}
@@ -1,14 +1,16 @@
 extern crate connecteer_translation;
-extern crate rmp_serde;
+extern crate embedded_io;
 extern crate serde;
 
-use connecteer_translation::{embedded_io::adapters::ToStd, Connection};
+use connecteer_translation::{
+    protocol::{MessagePack, Protocol},
+    Checksum, Connection, ConnectionBuilder, DeserializeError, Encryption, Framing, FramingError,
+    LengthPrefix, Limits, Overflow, OversizedPolicy, Role, SerializeError,
+};
+use std::{cell::Cell, rc::Rc};
 
 fn main() {
-    let mut connection = Connection::new_alloc(
-        |v| rmp_serde::Serializer::new(ToStd::new(v)),
-        |v| rmp_serde::Deserializer::new(ToStd::new(v)),
-    );
+    let mut connection = Connection::new_alloc(MessagePack);
 
     let before = Something {
         foo: "Hello".to_string(),
@@ -16,9 +18,334 @@ fn main() {
         baz: "World!".to_string(),
     };
     let val = connection.serialize(before.clone()).unwrap();
-    connection.feed_bytes(&val);
 
-    assert_eq!(before, connection.try_deserialize().unwrap());
+    // Half a message isn't an error, it only needs more bytes
+    connection.feed_bytes(&val[..val.len() / 2]);
+    assert!(matches!(
+        connection.try_deserialize(),
+        Err(DeserializeError::Incomplete { .. })
+    ));
+    connection.feed_bytes(&val[val.len() / 2..]);
+
+    assert_eq!(
+        (before, val.len()),
+        connection.try_deserialize_with_len().unwrap()
+    );
+
+    // Two messages sent back to back must come out one after the other, wherever the bytes were
+    // split when they were received
+    let new_connection = || Connection::new_alloc(MessagePack);
+    let messages = [
+        Something {
+            foo: "first".to_string(),
+            bar: 1,
+            baz: "message".to_string(),
+        },
+        Something {
+            foo: "second".to_string(),
+            bar: 70000,
+            baz: String::new(),
+        },
+    ];
+    let mut bytes = Vec::new();
+    for message in &messages {
+        bytes.extend(new_connection().serialize(message.clone()).unwrap());
+    }
+    for split in 0..=bytes.len() {
+        let mut connection = new_connection();
+        let mut received = Vec::new();
+        for chunk in [&bytes[..split], &bytes[split..]] {
+            connection.feed_bytes(chunk);
+            received.extend(connection.messages().map(Result::unwrap));
+        }
+        assert_eq!(received, messages, "split at byte {split}");
+    }
+
+    // Messages can be written into a slice, as long as it is big enough
+    let mut out = [0u8; 64];
+    for framing in [Framing::None, Framing::LengthPrefix(LengthPrefix::Varint)] {
+        let mut connection = new_connection().with_framing(framing);
+        let len = connection
+            .serialize_to_slice(&messages[0], &mut out)
+            .unwrap();
+        assert_eq!(
+            &out[..len],
+            connection.serialize(messages[0].clone()).unwrap()
+        );
+        assert!(matches!(
+            connection.serialize_to_slice(&messages[0], &mut out[..len - 1]),
+            Err(SerializeError::Write(Overflow))
+        ));
+    }
+
+    // A fixed size buffer only takes what fits in it, the rest has to be fed later
+    let mut connection = ConnectionBuilder::new()
+        .protocol(MessagePack)
+        .message::<Something>()
+        .static_ring_buffer::<24>()
+        .build();
+    let mut remaining = &bytes[..];
+    let mut received = Vec::new();
+    while !remaining.is_empty() {
+        let fed = connection.feed_bytes(remaining);
+        remaining = &remaining[fed..];
+        received.extend(connection.messages().map(Result::unwrap));
+    }
+    assert_eq!(received, messages);
+
+    // Strings can be borrowed straight from the buffer, which is released on the next call
+    let mut connection = ConnectionBuilder::new()
+        .protocol(MessagePack)
+        .incoming::<Borrowed<'static>>()
+        .outgoing::<Something>()
+        .build();
+    connection.feed_bytes(&bytes);
+    for message in &messages {
+        let borrowed = connection.try_deserialize_borrowed::<Borrowed>().unwrap();
+        assert_eq!((borrowed.foo, borrowed.baz), (&*message.foo, &*message.baz));
+    }
+    assert!(matches!(
+        connection.try_deserialize_borrowed::<Borrowed>(),
+        Err(DeserializeError::Incomplete { .. })
+    ));
+
+    // A map announcing 2^32 - 1 entries needs a byte for each key and value at least, which is
+    // refused as invalid when that doesn't fit in a `usize`
+    let header = [0xdf, 0xff, 0xff, 0xff, 0xff];
+    let expected = usize::try_from(2 * 0xffff_ffff_u64).ok();
+    let mut connection = new_connection();
+    connection.feed_bytes(&header);
+    match connection.try_deserialize_borrowed::<Borrowed>() {
+        Err(DeserializeError::Incomplete { needed }) => assert_eq!(needed, expected),
+        Err(DeserializeError::Invalid(_)) => assert_eq!(expected, None),
+        _ => panic!("the header alone isn't a message"),
+    }
+    // The deserializer itself only reports the first entry as missing in that case
+    let mut connection = new_connection();
+    connection.feed_bytes(&header);
+    assert!(matches!(
+        connection.try_deserialize(),
+        Err(DeserializeError::Incomplete { needed }) if expected.is_none() || needed == expected
+    ));
+
+    // A message over the limits is dropped, even before all of it is received, and the next one
+    // is read as usual
+    let framing = Framing::LengthPrefix(LengthPrefix::Varint);
+    let mut connection = new_connection().with_framing(framing).with_limits(Limits {
+        max_message: Some(32),
+        max_buffered: Some(64),
+        policy: OversizedPolicy::Resync,
+    });
+    let huge = Something {
+        foo: "Hello".repeat(100),
+        bar: 0,
+        baz: String::new(),
+    };
+    let mut bytes = connection.serialize(huge).unwrap();
+    bytes.extend(connection.serialize(messages[0].clone()).unwrap());
+    let (mut received, mut oversized) = (Vec::new(), 0);
+    for chunk in bytes.chunks(16) {
+        assert_eq!(connection.feed_bytes(chunk), chunk.len());
+        assert!(connection.buffer().len() <= 64);
+        loop {
+            match connection.try_deserialize() {
+                Ok(message) => received.push(message),
+                Err(DeserializeError::Oversized) => oversized += 1,
+                Err(DeserializeError::Incomplete { .. }) => break,
+                Err(e) => panic!("{e:?}"),
+            }
+        }
+    }
+    assert_eq!((oversized, received), (1, vec![messages[0].clone()]));
+
+    // Skipping after an oversized message was dropped doesn't drop anything more, whether all of
+    // it was received or not
+    for split in [16, bytes.len()] {
+        let mut connection = new_connection().with_framing(framing).with_limits(Limits {
+            max_message: Some(32),
+            max_buffered: None,
+            policy: OversizedPolicy::Resync,
+        });
+        connection.feed_bytes(&bytes[..split]);
+        assert!(matches!(
+            connection.try_deserialize(),
+            Err(DeserializeError::Oversized)
+        ));
+        assert_eq!(connection.skip_to_next_frame(), 0);
+        connection.feed_bytes(&bytes[split..]);
+        assert_eq!(connection.try_deserialize().unwrap(), messages[0]);
+    }
+
+    // A large message fed in small chunks isn't deserialized again on every one of its ~100
+    // chunks, but only once enough bytes are there: all of them with a length prefix, or what the
+    // deserializer was missing without framing
+    let large = Something {
+        foo: "Hello".repeat(20_000),
+        bar: 0,
+        baz: String::new(),
+    };
+    for (framing, max_attempts) in [(Framing::None, 4), (framing, 1)] {
+        let attempts = count_attempts(framing, &large);
+        assert!(attempts <= max_attempts, "{attempts} attempts");
+    }
+
+    // Nor is a message made of many small values (~180 chunks), which the deserializer reads one
+    // at a time: without framing, each attempt waits for at least a byte per value still missing
+    let samples: Vec<u64> = (0..20_000u64)
+        .map(|s| s.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+        .collect();
+    for (framing, max_attempts) in [(Framing::None, 40), (framing, 1)] {
+        let attempts = count_attempts(framing, &samples);
+        assert!(attempts <= max_attempts, "{attempts} attempts");
+    }
+
+    // A corrupt frame is skipped whole, and the following one is still readable
+    let mut connection = new_connection().with_framing(framing);
+    let mut bytes = connection.serialize(messages[0].clone()).unwrap();
+    let corrupt = bytes.len();
+    // 0xc1 is never used by MessagePack
+    bytes[1] = 0xc1;
+    bytes.extend(connection.serialize(messages[1].clone()).unwrap());
+    connection.feed_bytes(&bytes);
+    assert!(matches!(
+        connection.try_deserialize(),
+        Err(DeserializeError::Invalid(_))
+    ));
+    assert_eq!(connection.skip_to_next_frame(), corrupt);
+    assert_eq!(connection.try_deserialize().unwrap(), messages[1]);
+
+    // COBS frames, as sent over a serial link: a corrupt byte only loses its own message
+    let mut connection = ConnectionBuilder::new()
+        .protocol(MessagePack)
+        .message::<Something>()
+        .framing(Framing::Cobs)
+        .static_ring_buffer::<64>()
+        .build();
+    let mut out = [0u8; 64];
+    let mut bytes = Vec::new();
+    for message in &messages {
+        let len = connection.serialize_to_slice(message, &mut out).unwrap();
+        bytes.extend_from_slice(&out[..len]);
+    }
+    // The first code byte now points past the end of the frame
+    bytes[0] = 0xff;
+    connection.feed_bytes(&bytes);
+    assert!(matches!(
+        connection.try_deserialize(),
+        Err(DeserializeError::Framing(FramingError::InvalidCobs))
+    ));
+    assert_eq!(connection.try_deserialize().unwrap(), messages[1]);
+
+    // A flipped bit that still leaves a valid message is caught by the checksum
+    let mut connection = new_connection()
+        .with_framing(framing)
+        .with_checksum(Checksum::Crc32);
+    let mut bytes = connection.serialize(messages[0].clone()).unwrap();
+    let corrupt = bytes.len();
+    bytes.extend(connection.serialize(messages[1].clone()).unwrap());
+    // "first" becomes "girst"
+    let f = bytes.iter().position(|&byte| byte == b'f').unwrap();
+    bytes[f] ^= 0x01;
+    connection.feed_bytes(&bytes);
+    assert!(matches!(
+        connection.messages().next(),
+        Some(Err(DeserializeError::ChecksumMismatch))
+    ));
+    assert_eq!(connection.skip_to_next_frame(), corrupt);
+    assert_eq!(connection.try_deserialize().unwrap(), messages[1]);
+
+    // Over an untrusted link, both ends share a key and only accept authenticated messages
+    let key = [0x42; 32];
+    let mut sender = new_connection()
+        .with_framing(Framing::Cobs)
+        .with_encryption(Encryption::new(&key, Role::Initiator));
+    let mut receiver = new_connection()
+        .with_framing(Framing::Cobs)
+        .with_encryption(Encryption::new(&key, Role::Responder));
+    let first = sender.serialize(messages[0].clone()).unwrap();
+    receiver.feed_bytes(&first);
+    assert_eq!(receiver.try_deserialize().unwrap(), messages[0]);
+
+    // A replayed message is refused, as is a tampered one
+    receiver.feed_bytes(&first);
+    assert!(matches!(
+        receiver.try_deserialize(),
+        Err(DeserializeError::Unauthenticated)
+    ));
+    let mut second = sender.serialize(messages[1].clone()).unwrap();
+    second[20] ^= 0x01;
+    receiver.feed_bytes(&second);
+    assert!(matches!(
+        receiver.try_deserialize(),
+        Err(DeserializeError::Unauthenticated)
+    ));
+    receiver.feed_bytes(&sender.serialize(messages[1].clone()).unwrap());
+    assert_eq!(receiver.try_deserialize().unwrap(), messages[1]);
+
+    // Reversing a connection keeps its role, so it never takes the nonces of its peer: it refuses
+    // messages sent by the same role
+    let mut reversed = new_connection()
+        .with_framing(Framing::Cobs)
+        .with_encryption(Encryption::new(&key, Role::Initiator))
+        .reverse();
+    reversed.feed_bytes(&sender.serialize(messages[0].clone()).unwrap());
+    assert!(matches!(
+        reversed.try_deserialize(),
+        Err(DeserializeError::Unauthenticated)
+    ));
+}
+
+/// Feed `message` to a connection 1 KB at a time, returning how many times it was deserialized
+fn count_attempts<T>(framing: Framing, message: &T) -> usize
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + Clone + std::fmt::Debug,
+{
+    let attempts = Rc::new(Cell::new(0));
+    let mut connection = Connection::new_alloc(Counting {
+        inner: MessagePack,
+        attempts: attempts.clone(),
+    })
+    .with_framing(framing);
+    let bytes = connection.serialize(message.clone()).unwrap();
+    let mut received = Vec::new();
+    for chunk in bytes.chunks(1024) {
+        connection.feed_bytes(chunk);
+        received.extend(connection.messages().map(Result::unwrap));
+    }
+    assert_eq!(received, std::slice::from_ref(message));
+    attempts.get()
+}
+
+/// Counts the messages it tries to deserialize
+struct Counting {
+    inner: MessagePack,
+    attempts: Rc<Cell<usize>>,
+}
+
+impl Protocol for Counting {
+    type SerializerError = <MessagePack as Protocol>::SerializerError;
+    type DeserializerError = <MessagePack as Protocol>::DeserializerError;
+
+    fn serialize<T, W>(&mut self, value: &T, writer: W) -> Result<(), Self::SerializerError>
+    where
+        T: serde::Serialize + ?Sized,
+        W: embedded_io::blocking::Write,
+    {
+        self.inner.serialize(value, writer)
+    }
+
+    fn deserialize<T, R>(&mut self, reader: R) -> Result<T, Self::DeserializerError>
+    where
+        T: serde::de::DeserializeOwned,
+        R: embedded_io::blocking::Read<Error = core::convert::Infallible>,
+    {
+        self.attempts.set(self.attempts.get() + 1);
+        self.inner.deserialize(reader)
+    }
+
+    fn missing_bytes(&self, buffered: &[u8], short_by: usize) -> Option<usize> {
+        self.inner.missing_bytes(buffered, short_by)
+    }
 }
 
 #[derive(PartialEq, serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -27,3 +354,11 @@ struct Something {
     bar: usize,
     baz: String,
 }
+
+#[derive(serde::Deserialize)]
+struct Borrowed<'a> {
+    foo: &'a str,
+    #[allow(dead_code)]
+    bar: usize,
+    baz: &'a str,
+}
@@ -0,0 +1,94 @@
+//! Compares the allocations (and time) taken by the different ways of serializing a message.
+//!
+//! Run it in release mode: `cargo run --release -p serialize_bench`
+extern crate connecteer_translation;
+extern crate serde;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use connecteer_translation::{protocol::MessagePack, Connection};
+
+/// Counts every allocation made through the global allocator
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ROUNDS: usize = 1_000_000;
+
+fn main() {
+    let mut connection = Connection::new_alloc(MessagePack);
+    let message = Telemetry {
+        sensor: 42,
+        timestamp: 1_684_000_000,
+        values: [1.5, -2.25, 3.125, 0.0],
+    };
+
+    bench("serialize", || {
+        let mut total = 0;
+        for _ in 0..ROUNDS {
+            total += connection.serialize(message.clone()).unwrap().len();
+        }
+        total
+    });
+
+    let mut out = Vec::with_capacity(64);
+    bench("serialize_into (reused Vec)", || {
+        let mut total = 0;
+        for _ in 0..ROUNDS {
+            out.clear();
+            connection.serialize_into(&message, &mut out).unwrap();
+            total += out.len();
+        }
+        total
+    });
+
+    let mut out = [0u8; 64];
+    bench("serialize_to_slice", || {
+        let mut total = 0;
+        for _ in 0..ROUNDS {
+            total += connection.serialize_to_slice(&message, &mut out).unwrap();
+        }
+        total
+    });
+}
+
+fn bench(name: &str, f: impl FnOnce() -> usize) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let bytes = std::hint::black_box(f());
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{name:>28}: {:>6.1} ns/message, {:>5.2} allocations/message ({bytes} bytes)",
+        elapsed.as_nanos() as f64 / ROUNDS as f64,
+        allocations as f64 / ROUNDS as f64,
+    );
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct Telemetry {
+    sensor: u32,
+    timestamp: u64,
+    values: [f32; 4],
+}
@@ -0,0 +1,174 @@
+extern crate connecteer_translation;
+extern crate serde;
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+
+use connecteer_translation::{
+    protocol::{Json, MessagePack, Protocol},
+    stream::StreamError,
+    Connection, ConnectionBuilder, Error, Framing, JsonConnection, LengthPrefix, StreamConnection,
+};
+
+fn main() {
+    let messages = (0..100)
+        .map(|i| Something {
+            foo: "Hello".repeat(i % 7),
+            bar: i * 1000,
+        })
+        .collect::<Vec<_>>();
+
+    // TCP, with a thread on the other side echoing every message back
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let echo = std::thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut server =
+            StreamConnection::<_, _, Something>::new(socket, Connection::new_alloc(MessagePack));
+        loop {
+            match server.recv() {
+                Ok(message) => server.send(&message).unwrap(),
+                Err(StreamError::Closed) => break,
+                Err(e) => panic!("{e:?}"),
+            }
+        }
+    });
+    let mut client = StreamConnection::new(
+        TcpStream::connect(addr).unwrap(),
+        Connection::new_alloc(MessagePack),
+    );
+    for message in &messages {
+        client.send(message).unwrap();
+        assert_eq!(&client.recv().unwrap(), message);
+    }
+    client
+        .into_inner()
+        .shutdown(std::net::Shutdown::Both)
+        .unwrap();
+    echo.join().unwrap();
+
+    // Unix sockets, sending everything before reading anything back
+    let (a, b) = UnixStream::pair().unwrap();
+    let framing = Framing::LengthPrefix(LengthPrefix::U32Be);
+    let mut a = StreamConnection::new(a, Connection::new_alloc(Json).with_framing(framing));
+    let mut b = StreamConnection::new(
+        b,
+        JsonConnection::<Something>::new_alloc(Json).with_framing(framing),
+    );
+    for message in &messages {
+        a.send(message).unwrap();
+    }
+    drop(a);
+    for message in &messages {
+        assert_eq!(&b.recv().unwrap(), message);
+    }
+    assert!(matches!(b.recv(), Err(StreamError::Closed)));
+
+    // A non blocking socket with nothing to read gives `WouldBlock` without losing anything
+    let (a, b) = UnixStream::pair().unwrap();
+    b.set_nonblocking(true).unwrap();
+    let mut a = StreamConnection::new(a, Connection::new_alloc(MessagePack));
+    let mut b = StreamConnection::<_, _, Something>::new(b, Connection::new_alloc(MessagePack));
+    assert!(matches!(b.recv(), Err(StreamError::Io(e)) if e.kind() == ErrorKind::WouldBlock));
+    a.send(&messages[1]).unwrap();
+    assert_eq!(b.recv().unwrap(), messages[1]);
+
+    // An in memory pipe, handing out a single byte per read, and cut in the middle of a message
+    let mut pipe = StreamConnection::new(Pipe::default(), Connection::new_alloc(MessagePack));
+    pipe.send(&messages[3]).unwrap();
+    pipe.send(&messages[4]).unwrap();
+    pipe.get_mut().0.pop_back();
+    assert_eq!(pipe.recv().unwrap(), messages[3]);
+    assert!(matches!(pipe.recv(), Err(StreamError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof));
+
+    // A client sending requests and receiving responses, the server being its reverse
+    let (a, b) = UnixStream::pair().unwrap();
+    let connection = || {
+        ConnectionBuilder::new()
+            .protocol(Json)
+            .incoming::<Sum>()
+            .outgoing::<Add>()
+            .build()
+    };
+    let mut client = StreamConnection::new(a, connection());
+    let mut server = StreamConnection::new(b, connection().reverse());
+    client.send(&Add(2, 3)).unwrap();
+    let Add(x, y) = server.recv().unwrap();
+    server.send(&Sum(x + y)).unwrap();
+    assert_eq!(client.recv().unwrap(), Sum(5));
+
+    // The errors of a connection and of a stream all go into the same `Error` with `?`, which
+    // keeps the error of the deserializer as its source
+    let mut pipe = StreamConnection::new(Pipe::default(), Connection::new_alloc(MessagePack));
+    let bytes = Connection::new_alloc(MessagePack)
+        .serialize(messages[5].clone())
+        .unwrap();
+    assert_eq!(relay(&bytes, &mut pipe).unwrap(), messages[5]);
+    assert_eq!(pipe.recv().unwrap(), messages[5]);
+    assert!(matches!(
+        relay(&bytes[..1], &mut pipe),
+        Err(Error::Incomplete { .. })
+    ));
+    // 0xc1 is never used by MessagePack
+    let error: Box<dyn std::error::Error> = relay(&[0xc1], &mut pipe).unwrap_err().into();
+    assert!(error.source().is_some());
+    assert!(error
+        .to_string()
+        .ends_with(&error.source().unwrap().to_string()));
+}
+
+/// Read a message out of `bytes` and send it on `stream`
+fn relay(
+    bytes: &[u8],
+    stream: &mut StreamConnection<Pipe, MessagePack, Something>,
+) -> Result<
+    Something,
+    Error<<MessagePack as Protocol>::SerializerError, <MessagePack as Protocol>::DeserializerError>,
+> {
+    let mut connection = Connection::new_alloc(MessagePack);
+    connection.feed_bytes(bytes);
+    let message = connection.try_deserialize()?;
+    stream.send(&message)?;
+    Ok(message)
+}
+
+/// Bytes written to the pipe are read back from it, one at a time
+#[derive(Default)]
+struct Pipe(VecDeque<u8>);
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match (self.0.pop_front(), buf.first_mut()) {
+            (Some(byte), Some(out)) => {
+                *out = byte;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct Something {
+    foo: String,
+    bar: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Add(u32, u32);
+
+#[derive(PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+struct Sum(u32);
@@ -0,0 +1,217 @@
+//! Compares the throughput of JSON and MessagePack over a [`Connection`], for many small messages
+//! and for a few large ones.
+//!
+//! Every case is also run with a buffer reading one byte at a time, like `RingBuffer` used to, as
+//! a baseline for the slice based reads. `serde_json` reads its input one byte at a time, so
+//! JSON gains little from them, unlike the strings and arrays of MessagePack.
+//!
+//! Run it in release mode: `cargo run --release -p throughput_bench`
+extern crate connecteer_translation;
+extern crate serde;
+
+use std::collections::{vec_deque, VecDeque};
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use connecteer_translation::{
+    buffer::{Buffer, RingBuffer},
+    embedded_io,
+    protocol::{Json, MessagePack, Protocol},
+    Connection, Framing, LengthPrefix, DEFAULT_BUFFER_SIZE,
+};
+
+/// Bytes handed to the connection at once, as a socket read would
+const CHUNK: usize = 64 * 1024;
+
+/// Each case is timed this many times with each buffer, keeping the fastest run
+const RUNS: usize = 3;
+
+fn main() {
+    let small: Vec<_> = (0..100_000)
+        .map(|i| Telemetry {
+            sensor: i,
+            timestamp: 1_684_000_000 + u64::from(i),
+            values: [1.5, -2.25, 3.125, 0.0],
+        })
+        .collect();
+    let large: Vec<_> = (0..8)
+        .map(|i| Recording {
+            name: format!("recording {i}"),
+            samples: (0..128 * 1024)
+                .map(|s: u64| s.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+                .collect(),
+        })
+        .collect();
+    let text: Vec<_> = (0..8)
+        .map(|i| Document {
+            title: format!("document {i}"),
+            body: "lorem ipsum dolor sit amet ".repeat(40_000),
+        })
+        .collect();
+
+    for framing in [Framing::None, Framing::LengthPrefix(LengthPrefix::Varint)] {
+        println!("{framing:?}");
+        if framing == Framing::None {
+            println!(
+                "  (unframed JSON is parsed again from the start on every chunk of a message)"
+            );
+        }
+        bench("JSON, small messages", Json, framing, &small);
+        bench("MessagePack, small messages", MessagePack, framing, &small);
+        bench("JSON, 1 MB messages", Json, framing, &large);
+        bench("MessagePack, 1 MB messages", MessagePack, framing, &large);
+        bench("JSON, 1 MB strings", Json, framing, &text);
+        bench("MessagePack, 1 MB strings", MessagePack, framing, &text);
+    }
+}
+
+/// Serialize every message, then time how long it takes to receive all of them, with the
+/// `RingBuffer` and with the per-byte baseline
+fn bench<P, T>(name: &str, protocol: P, framing: Framing, messages: &[T])
+where
+    P: Protocol + Copy,
+    T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + Clone,
+{
+    let mut sender = Connection::new_alloc(protocol).with_framing(framing);
+    let mut bytes = Vec::new();
+    for message in messages {
+        sender.serialize_into(message, &mut bytes).unwrap();
+    }
+
+    let mut ring = Duration::MAX;
+    let mut per_byte = Duration::MAX;
+    for _ in 0..RUNS {
+        per_byte = per_byte.min(receive(
+            protocol,
+            framing,
+            &bytes,
+            messages,
+            PerByteBuffer::default,
+        ));
+        ring = ring.min(receive(protocol, framing, &bytes, messages, || {
+            RingBuffer::new(DEFAULT_BUFFER_SIZE)
+        }));
+    }
+    println!(
+        "{name:>30}: {:>8.1} MB/s, {:>10.0} messages/s, {:>5.2}x the per-byte reader ({} bytes)",
+        bytes.len() as f64 / 1e6 / secs(ring),
+        messages.len() as f64 / secs(ring),
+        secs(per_byte) / secs(ring),
+        bytes.len(),
+    );
+}
+
+/// Feed `bytes` to a connection in chunks, returning how long it took to receive `messages` back
+fn receive<P, T, B>(
+    protocol: P,
+    framing: Framing,
+    bytes: &[u8],
+    messages: &[T],
+    buffer: impl FnOnce() -> B,
+) -> Duration
+where
+    P: Protocol,
+    T: serde::de::DeserializeOwned + PartialEq,
+    B: Buffer,
+{
+    let mut receiver = Connection::<P, T, T, B, _, _>::new(protocol, Vec::<u8>::new, buffer())
+        .with_framing(framing);
+    let mut received = Vec::with_capacity(messages.len());
+    let start = Instant::now();
+    for chunk in bytes.chunks(CHUNK) {
+        receiver.feed_bytes(chunk);
+        received.extend(receiver.messages().map(Result::unwrap));
+    }
+    let elapsed = start.elapsed();
+    assert!(received == messages, "messages changed on the way");
+    elapsed
+}
+
+fn secs(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// A ring buffer whose reader copies one byte at a time, as `RingBuffer` used to
+#[derive(Default)]
+struct PerByteBuffer {
+    bytes_read: usize,
+    inner: VecDeque<u8>,
+}
+
+struct PerByteRead<'buf> {
+    bytes_read: &'buf mut usize,
+    iter: vec_deque::Iter<'buf, u8>,
+}
+
+impl embedded_io::Io for PerByteRead<'_> {
+    type Error = Infallible;
+}
+
+impl embedded_io::blocking::Read for PerByteRead<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self
+            .iter
+            .by_ref()
+            .take(buf.len())
+            .zip(buf.iter_mut())
+            .map(|(&r, w)| *w = r)
+            .inspect(|()| *self.bytes_read += 1)
+            .count())
+    }
+}
+
+impl Buffer for PerByteBuffer {
+    type Reader<'a> = PerByteRead<'a>;
+
+    fn feed_bytes(&mut self, bytes: &[u8]) -> usize {
+        self.inner.extend(bytes);
+        bytes.len()
+    }
+
+    fn get_read(&mut self) -> Self::Reader<'_> {
+        PerByteRead {
+            bytes_read: &mut self.bytes_read,
+            iter: self.inner.iter(),
+        }
+    }
+
+    fn discard_read_bytes(&mut self) {
+        self.inner.drain(..self.bytes_read);
+        self.bytes_read = 0;
+    }
+
+    fn keep_read_bytes(&mut self) {
+        self.bytes_read = 0;
+    }
+
+    fn contiguous(&mut self) -> &mut [u8] {
+        self.inner.make_contiguous()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Clone)]
+struct Telemetry {
+    sensor: u32,
+    timestamp: u64,
+    values: [f32; 4],
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Clone)]
+struct Recording {
+    name: String,
+    samples: Vec<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Clone)]
+struct Document {
+    title: String,
+    body: String,
+}